@@ -0,0 +1,181 @@
+use chrono::Local;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::{poll_fn, Future};
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+#[derive(Default)]
+struct TokenInner {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+/// 取消标记, 批次和其中的每个任务各有一个
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<TokenInner>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        self.0.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// 等待直到被取消
+    pub async fn cancelled(&self) {
+        loop {
+            let mut notified = pin!(self.0.notify.notified());
+            // 先登记再检查, 避免错过检查之后、等待之前发出的通知
+            notified.as_mut().enable();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// 执行 fut, 期间被取消时放弃并返回 None
+    pub async fn or_cancelled<F: Future>(&self, fut: F) -> Option<F::Output> {
+        let mut fut = pin!(fut);
+        let mut cancelled = pin!(self.cancelled());
+        poll_fn(|cx| {
+            if let Poll::Ready(output) = fut.as_mut().poll(cx) {
+                return Poll::Ready(Some(output));
+            }
+            if cancelled.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(None);
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchInfo {
+    pub batch_id: String,
+    pub schedule_id: String,
+    pub started_at: String,
+    pub cancel_requested: bool,
+}
+
+struct BatchEntry {
+    info: BatchInfo,
+    token: CancelToken,
+    /// 未结束的任务, key 为 task_id
+    tasks: HashMap<String, CancelToken>,
+}
+
+/// 正在执行的批次. 批次与定时循环分开运行, 修改或停止定时任务不会中断已开始的批次
+#[derive(Clone, Default)]
+pub struct ActiveBatches(Arc<Mutex<HashMap<String, BatchEntry>>>);
+
+/// 批次执行期间持有, 结束(含异常退出)时自动从登记中移除
+pub struct BatchGuard {
+    batches: ActiveBatches,
+    pub id: String,
+    pub token: CancelToken,
+}
+
+impl Drop for BatchGuard {
+    fn drop(&mut self) {
+        self.batches.0.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl BatchGuard {
+    /// 登记批次中的任务, 批次已取消时返回的标记也是已取消状态
+    pub fn register_task(&self, task_id: &str) -> TaskGuard {
+        let token = CancelToken::default();
+        if let Some(entry) = self.batches.0.lock().unwrap().get_mut(&self.id) {
+            if entry.token.is_cancelled() {
+                token.cancel();
+            }
+            entry.tasks.insert(task_id.to_string(), token.clone());
+        }
+        TaskGuard {
+            batches: self.batches.clone(),
+            batch_id: self.id.clone(),
+            task_id: task_id.to_string(),
+            token,
+        }
+    }
+}
+
+/// 任务结束时自动从所属批次中移除
+pub struct TaskGuard {
+    batches: ActiveBatches,
+    batch_id: String,
+    task_id: String,
+    pub token: CancelToken,
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        if let Some(entry) = self.batches.0.lock().unwrap().get_mut(&self.batch_id) {
+            entry.tasks.remove(&self.task_id);
+        }
+    }
+}
+
+impl ActiveBatches {
+    pub fn begin(&self, schedule_id: &str) -> BatchGuard {
+        let id = Uuid::now_v7().to_string();
+        let token = CancelToken::default();
+        let info = BatchInfo {
+            batch_id: id.clone(),
+            schedule_id: schedule_id.to_string(),
+            started_at: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            cancel_requested: false,
+        };
+        self.0.lock().unwrap().insert(
+            id.clone(),
+            BatchEntry {
+                info,
+                token: token.clone(),
+                tasks: HashMap::new(),
+            },
+        );
+        BatchGuard {
+            batches: self.clone(),
+            id,
+            token,
+        }
+    }
+
+    /// 请求取消指定批次或指定定时任务的批次(都未指定时取消全部)及其中所有未结束的任务,
+    /// 返回被取消的批次
+    pub fn cancel(&self, batch_id: Option<&str>, schedule_id: Option<&str>) -> Vec<BatchInfo> {
+        let mut map = self.0.lock().unwrap();
+        map.iter_mut()
+            .filter(|(id, _)| batch_id.is_none_or(|b| b == id.as_str()))
+            .filter(|(_, entry)| schedule_id.is_none_or(|s| s == entry.info.schedule_id))
+            .map(|(_, entry)| {
+                entry.token.cancel();
+                entry.tasks.values().for_each(CancelToken::cancel);
+                entry.info.cancel_requested = true;
+                entry.info.clone()
+            })
+            .collect()
+    }
+
+    /// 请求取消单个任务, 任务不在执行中时返回 false
+    pub fn cancel_task(&self, task_id: &str) -> bool {
+        let map = self.0.lock().unwrap();
+        match map.values().find_map(|entry| entry.tasks.get(task_id)) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,428 @@
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+
+/// 屏蔽规则, 命中时定时任务不执行
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BlackoutRule {
+    /// 时间范围 [start, end), 例如节假日
+    DateRange {
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+        #[serde(default)]
+        summary: Option<String>,
+    },
+    /// 每周固定时段, weekdays 为 1(周一) ~ 7(周日), end 不大于 start 时表示跨天
+    Weekly {
+        weekdays: Vec<u32>,
+        start: NaiveTime,
+        end: NaiveTime,
+    },
+}
+
+impl BlackoutRule {
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            BlackoutRule::DateRange { start, end, .. } if end <= start => {
+                Err(format!("屏蔽时间范围无效: {} ~ {}", start, end))
+            }
+            BlackoutRule::Weekly { weekdays, .. } if weekdays.is_empty() => {
+                Err("每周屏蔽时段至少选择一天".to_string())
+            }
+            BlackoutRule::Weekly { weekdays, .. }
+                if weekdays.iter().any(|d| !(1..=7).contains(d)) =>
+            {
+                Err("星期只能为 1(周一) ~ 7(周日)".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn matches(&self, at: &NaiveDateTime) -> bool {
+        match self {
+            BlackoutRule::DateRange { start, end, .. } => start <= at && at < end,
+            BlackoutRule::Weekly {
+                weekdays,
+                start,
+                end,
+            } => {
+                let weekday = at.weekday().number_from_monday();
+                let time = at.time();
+                if start < end {
+                    weekdays.contains(&weekday) && *start <= time && time < *end
+                } else {
+                    // 跨天时段: 当天 start 之后, 或前一天开始、当天 end 之前
+                    let previous = if weekday == 1 { 7 } else { weekday - 1 };
+                    (weekdays.contains(&weekday) && *start <= time)
+                        || (weekdays.contains(&previous) && time < *end)
+                }
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            BlackoutRule::DateRange {
+                start,
+                end,
+                summary,
+            } => match summary {
+                Some(summary) => format!("{} ({} ~ {})", summary, start, end),
+                None => format!("{} ~ {}", start, end),
+            },
+            BlackoutRule::Weekly {
+                weekdays,
+                start,
+                end,
+            } => format!("每周{:?} {} ~ {}", weekdays, start, end),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CalendarRecord {
+    pub id: String,
+    pub name: String,
+    pub rules: Vec<BlackoutRule>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CalendarInput {
+    pub name: String,
+    #[serde(default)]
+    pub rules: Vec<BlackoutRule>,
+}
+
+impl CalendarRecord {
+    pub fn new(id: String, input: CalendarInput) -> Result<Self, String> {
+        let now = Local::now().to_rfc3339();
+        let mut record = CalendarRecord {
+            id,
+            name: String::new(),
+            rules: Vec::new(),
+            created_at: now.clone(),
+            updated_at: now,
+        };
+        record.apply(input)?;
+        Ok(record)
+    }
+
+    pub fn apply(&mut self, input: CalendarInput) -> Result<(), String> {
+        let name = input.name.trim();
+        if name.is_empty() {
+            return Err("日历名称不能为空".to_string());
+        }
+        for rule in &input.rules {
+            rule.validate()?;
+        }
+        self.name = name.to_string();
+        self.rules = input.rules;
+        self.updated_at = Local::now().to_rfc3339();
+        Ok(())
+    }
+
+    /// 命中屏蔽规则时返回原因
+    pub fn blocked_reason(&self, at: &DateTime<Local>) -> Option<String> {
+        let at = at.naive_local();
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(&at))
+            .map(|rule| format!("{}: {}", self.name, rule.describe()))
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let rules: String = row.get(2)?;
+        Ok(CalendarRecord {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            rules: serde_json::from_str(&rules).unwrap_or_default(),
+            created_at: row.get(3)?,
+            updated_at: row.get(4)?,
+        })
+    }
+}
+
+pub fn create_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        r#"
+        create table if not exists calendars(
+         id text primary key,
+         name text not null,
+         rules text not null,
+         created_at text not null,
+         updated_at text not null
+        )
+    "#,
+        (),
+    )?;
+    Ok(())
+}
+
+const SELECT_COLUMNS: &str = "SELECT id, name, rules, created_at, updated_at FROM calendars";
+
+pub fn load_calendar(conn: &Connection, id: &str) -> rusqlite::Result<Option<CalendarRecord>> {
+    conn.query_row(
+        &format!("{} WHERE id = ?1", SELECT_COLUMNS),
+        params![id],
+        CalendarRecord::from_row,
+    )
+    .optional()
+}
+
+pub fn list_calendars(conn: &Connection) -> rusqlite::Result<Vec<CalendarRecord>> {
+    let mut stmt = conn.prepare(&format!("{} ORDER BY created_at", SELECT_COLUMNS))?;
+    let rows = stmt.query_map((), CalendarRecord::from_row)?;
+    rows.collect()
+}
+
+/// 按ID加载定时任务关联的日历, 已删除的日历直接忽略
+pub fn load_calendars(conn: &Connection, ids: &[String]) -> rusqlite::Result<Vec<CalendarRecord>> {
+    let mut calendars = Vec::new();
+    for id in ids {
+        if let Some(calendar) = load_calendar(conn, id)? {
+            calendars.push(calendar);
+        }
+    }
+    Ok(calendars)
+}
+
+pub fn save_calendar(conn: &Connection, record: &CalendarRecord) -> rusqlite::Result<()> {
+    let rules = serde_json::to_string(&record.rules).unwrap_or_else(|_| "[]".to_string());
+    conn.execute(
+        r#"
+        INSERT INTO calendars (id, name, rules, created_at, updated_at)
+        VALUES (?1, ?2, ?3, ?4, ?5)
+        ON CONFLICT(id) DO UPDATE SET
+            name = excluded.name,
+            rules = excluded.rules,
+            updated_at = excluded.updated_at
+        "#,
+        params![
+            record.id,
+            record.name,
+            rules,
+            record.created_at,
+            record.updated_at
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn delete_calendar(conn: &Connection, id: &str) -> rusqlite::Result<usize> {
+    conn.execute("DELETE FROM calendars WHERE id = ?1", params![id])
+}
+
+/// 任一日历命中时返回屏蔽原因
+pub fn blocked_reason(calendars: &[CalendarRecord], at: &DateTime<Local>) -> Option<String> {
+    calendars.iter().find_map(|c| c.blocked_reason(at))
+}
+
+/// 解析 ICS 文件中的 VEVENT 为屏蔽时间范围.
+/// 仅支持明确列出日期的事件(节假日日历通常如此), 不展开 RRULE 重复规则
+pub fn parse_ics(content: &str) -> Result<Vec<BlackoutRule>, String> {
+    // 以空格或制表符开头的行是上一行的续行, 折行处的空格属于内容
+    let mut lines: Vec<String> = Vec::new();
+    for line in content.lines() {
+        match line.strip_prefix([' ', '\t']) {
+            Some(rest) if !lines.is_empty() => lines.last_mut().unwrap().push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    let mut rules = Vec::new();
+    let mut event: Option<(Option<IcsTime>, Option<IcsTime>, Option<String>)> = None;
+    for line in &lines {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim_end();
+        let (name, params) = key.split_once(';').unwrap_or((key, ""));
+        match (name, value) {
+            ("BEGIN", "VEVENT") => event = Some((None, None, None)),
+            ("END", "VEVENT") => {
+                let Some((start, end, summary)) = event.take() else {
+                    continue;
+                };
+                let Some(start) = start else {
+                    return Err(format!(
+                        "ICS事件缺少DTSTART: {}",
+                        summary.unwrap_or_default()
+                    ));
+                };
+                let end = match (end, &start) {
+                    (Some(end), _) => end.value,
+                    // 全天事件没有DTEND时持续一天
+                    (
+                        None,
+                        IcsTime {
+                            all_day: true,
+                            value,
+                        },
+                    ) => *value + chrono::Duration::days(1),
+                    (None, _) => continue,
+                };
+                if end > start.value {
+                    rules.push(BlackoutRule::DateRange {
+                        start: start.value,
+                        end,
+                        summary,
+                    });
+                }
+            }
+            ("DTSTART", _) | ("DTEND", _) => {
+                if let Some(event) = event.as_mut() {
+                    let time = parse_ics_time(params, value)?;
+                    if name == "DTSTART" {
+                        event.0 = Some(time);
+                    } else {
+                        event.1 = Some(time);
+                    }
+                }
+            }
+            ("SUMMARY", _) => {
+                if let Some(event) = event.as_mut() {
+                    event.2 = Some(value.replace("\\,", ",").replace("\\n", " "));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(rules)
+}
+
+struct IcsTime {
+    value: NaiveDateTime,
+    all_day: bool,
+}
+
+fn parse_ics_time(params: &str, value: &str) -> Result<IcsTime, String> {
+    let invalid = || format!("无法解析ICS时间: {}", value);
+    if params.contains("VALUE=DATE") && !params.contains("VALUE=DATE-TIME") || value.len() == 8 {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d").map_err(|_| invalid())?;
+        return Ok(IcsTime {
+            value: date.and_time(NaiveTime::MIN),
+            all_day: true,
+        });
+    }
+    // UTC时间转换为本地时间, 带TZID或不带时区的按本地时间处理
+    let value = match value.strip_suffix('Z') {
+        Some(utc) => {
+            let utc = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").map_err(|_| invalid())?;
+            Utc.from_utc_datetime(&utc)
+                .with_timezone(&Local)
+                .naive_local()
+        }
+        None => NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").map_err(|_| invalid())?,
+    };
+    Ok(IcsTime {
+        value,
+        all_day: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date_time(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 10, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn date_range(rule: &BlackoutRule) -> (NaiveDateTime, NaiveDateTime, Option<&str>) {
+        match rule {
+            BlackoutRule::DateRange {
+                start,
+                end,
+                summary,
+            } => (*start, *end, summary.as_deref()),
+            other => panic!("不是时间范围: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_ics_joins_folded_lines() {
+        let content = "BEGIN:VCALENDAR\r\n\
+            BEGIN:VEVENT\r\n\
+            DTSTART;VALUE=DATE:20261001\r\n\
+            DTEND;VALUE=DATE:20261008\r\n\
+            SUMMARY:国庆节\\, \r\n\
+            \t中秋节\r\n\
+            END:VEVENT\r\n\
+            END:VCALENDAR\r\n";
+        let rules = parse_ics(content).unwrap();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(
+            date_range(&rules[0]),
+            (
+                date_time(1, 0, 0),
+                date_time(8, 0, 0),
+                Some("国庆节, 中秋节")
+            )
+        );
+    }
+
+    #[test]
+    fn parse_ics_all_day_event_without_end_lasts_one_day() {
+        let content = "BEGIN:VEVENT\nDTSTART;VALUE=DATE:20261010\nEND:VEVENT\n\
+            BEGIN:VEVENT\nDTSTART:20261011\nSUMMARY:补班\nEND:VEVENT\n";
+        let rules = parse_ics(content).unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(
+            date_range(&rules[0]),
+            (date_time(10, 0, 0), date_time(11, 0, 0), None)
+        );
+        assert_eq!(
+            date_range(&rules[1]),
+            (date_time(11, 0, 0), date_time(12, 0, 0), Some("补班"))
+        );
+    }
+
+    #[test]
+    fn parse_ics_skips_timed_event_without_end() {
+        let content = "BEGIN:VEVENT\nDTSTART:20261010T090000\nEND:VEVENT\n";
+        assert!(parse_ics(content).unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_ics_converts_utc_to_local() {
+        let content = "BEGIN:VEVENT\n\
+            DTSTART:20261010T010000Z\n\
+            DTEND:20261010T030000Z\n\
+            END:VEVENT\n";
+        let rules = parse_ics(content).unwrap();
+        let local = |hour| {
+            Utc.with_ymd_and_hms(2026, 10, 10, hour, 0, 0)
+                .unwrap()
+                .with_timezone(&Local)
+                .naive_local()
+        };
+        assert_eq!(date_range(&rules[0]), (local(1), local(3), None));
+    }
+
+    #[test]
+    fn parse_ics_keeps_floating_and_tzid_times() {
+        let content = "BEGIN:VEVENT\n\
+            DTSTART;TZID=Asia/Shanghai:20261010T090000\n\
+            DTEND:20261010T180000\n\
+            END:VEVENT\n";
+        let rules = parse_ics(content).unwrap();
+        assert_eq!(
+            date_range(&rules[0]),
+            (date_time(10, 9, 0), date_time(10, 18, 0), None)
+        );
+    }
+
+    #[test]
+    fn parse_ics_requires_start() {
+        let content = "BEGIN:VEVENT\nSUMMARY:元旦\nEND:VEVENT\n";
+        assert!(parse_ics(content).unwrap_err().contains("元旦"));
+        let content = "BEGIN:VEVENT\nDTSTART:2026-10-10\nEND:VEVENT\n";
+        assert!(parse_ics(content).is_err());
+    }
+}
@@ -0,0 +1,22 @@
+use rusqlite::Connection;
+
+/// 旧版本创建的表缺少新增列时, 通过 ALTER TABLE 补齐
+pub fn ensure_column(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    decl: &str,
+) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let mut rows = stmt.query(())?;
+    while let Some(row) = rows.next()? {
+        if row.get::<_, String>(1)? == column {
+            return Ok(());
+        }
+    }
+    conn.execute(
+        &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl),
+        (),
+    )?;
+    Ok(())
+}
@@ -0,0 +1,924 @@
+use log::{info, warn};
+use rusqlite::params;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use uuid::Uuid;
+
+use crate::batch::{BatchGuard, CancelToken};
+use crate::filter::{Criterion, RecordFilter};
+use crate::models::{AutomatorLog, AutomatorResponse, WpsRecord};
+use crate::retry::{self, Failure, RetryPolicy};
+use crate::scheduler::RowFilter;
+use crate::settings::{
+    ConnectionSettings, DispatchSettings, RetrySettings, Settings, SettingsState, WpsMapping,
+};
+use crate::tasks::{self, TaskState};
+use crate::{wps_reader, Db};
+
+/// 手动执行或定时任务触发时的批次选项
+#[derive(Debug, Clone, Default)]
+pub struct BatchOptions {
+    pub filter: RowFilter,
+    /// 为 true 时已成功的行也重新下发(在 WPS 中清空执行结果后重新渲染),
+    /// 排队中、执行中的行仍然跳过
+    pub rerun: bool,
+}
+
+/// 单行的处理结果, state 为任务的最终状态. 缺少必要字段或已有任务的行不创建任务, 状态为 skipped.
+/// reason 为失败、取消或跳过的原因, 成功但回写WPS失败时也会记录
+#[derive(Debug, Clone, Serialize)]
+pub struct RowResult {
+    pub row_id: String,
+    pub sku: String,
+    pub module: String,
+    pub task_id: Option<String>,
+    pub state: TaskState,
+    pub reason: Option<String>,
+}
+
+/// 批次执行结果, 每一行单独处理, 某一行失败不影响其余行
+#[derive(Debug, Default, Serialize)]
+pub struct BatchSummary {
+    /// 已下发给 automator 的行数
+    pub dispatched: usize,
+    pub succeeded: usize,
+    /// 失败与超时的行数
+    pub failed: usize,
+    pub skipped: usize,
+    /// 批次取消时尚未开始、被取消的任务数
+    pub cancelled_tasks: usize,
+    /// 不符合筛选条件的行数, 不计入 rows
+    pub filtered: usize,
+    pub cancelled: bool,
+    /// 读取的页数
+    pub pages: usize,
+    /// 达到每批次最多下发的行数, 其余行留到下一次执行
+    pub limit_reached: bool,
+    /// 读取后续页失败时的原因, 已读取的行照常执行
+    pub fetch_error: Option<String>,
+    pub rows: Vec<RowResult>,
+}
+
+impl BatchSummary {
+    fn record(&mut self, row: RowResult) {
+        match row.state {
+            TaskState::Succeeded => self.succeeded += 1,
+            TaskState::Failed | TaskState::TimedOut => self.failed += 1,
+            TaskState::Cancelled => self.cancelled_tasks += 1,
+            TaskState::Skipped | TaskState::Queued | TaskState::Running => self.skipped += 1,
+        }
+        if row.task_id.is_some() && !matches!(row.state, TaskState::Cancelled | TaskState::Skipped)
+        {
+            self.dispatched += 1;
+        }
+        self.rows.push(row);
+    }
+
+    pub fn message(&self) -> String {
+        let mut message = format!(
+            "{}, 下发 {} 条, 成功 {} 条, 失败 {} 条, 跳过 {} 条, 取消 {} 条",
+            if self.cancelled {
+                "批次已取消"
+            } else {
+                "任务执行完成"
+            },
+            self.dispatched,
+            self.succeeded,
+            self.failed,
+            self.skipped,
+            self.cancelled_tasks
+        );
+        if self.limit_reached {
+            message.push_str(", 已达到每批次最多下发的行数, 其余行留到下一次执行");
+        }
+        if let Some(e) = &self.fetch_error {
+            message.push_str(&format!(", 读取后续数据失败: {}", e));
+        }
+        message
+    }
+}
+
+/// 一条待下发的 WPS 记录
+struct RowJob {
+    row_id: String,
+    sku: String,
+    module: String,
+    create_time: String,
+    fields: serde_json::Value,
+}
+
+impl RowJob {
+    fn result(
+        &self,
+        task_id: Option<String>,
+        state: TaskState,
+        reason: Option<String>,
+    ) -> RowResult {
+        RowResult {
+            row_id: self.row_id.clone(),
+            sku: self.sku.clone(),
+            module: self.module.clone(),
+            task_id,
+            state,
+            reason,
+        }
+    }
+}
+
+/// 试运行中计划下发的一行, problems 不为空时该行不会下发
+#[derive(Debug, Serialize)]
+pub struct PlannedRow {
+    pub row_id: String,
+    pub sku: String,
+    pub module: String,
+    /// 将要 POST 给 automator 的内容
+    pub payload: serde_json::Value,
+    pub problems: Vec<String>,
+}
+
+/// 试运行结果: 只读取和校验, 不创建任务、不请求 automator、不回写 WPS
+#[derive(Debug, Default, Serialize)]
+pub struct BatchPlan {
+    /// 校验通过、会被下发的行数
+    pub dispatchable: usize,
+    /// 不符合筛选条件的行数, 不计入 rows
+    pub filtered: usize,
+    /// 读取的页数
+    pub pages: usize,
+    /// 达到每批次最多下发的行数, 其余行留到下一次执行
+    pub limit_reached: bool,
+    /// 读取后续页失败时的原因, 已读取的行照常计划
+    pub fetch_error: Option<String>,
+    pub rows: Vec<PlannedRow>,
+}
+
+/// 分页读取 WPS 中待执行的记录, 每一页的临时性失败按 WPS 重试策略重试.
+/// 能由 Airscript 判断的筛选条件随查询一起提交, 减少读取的行数; 所有条件仍会在本地再判断一次
+struct RowPager<'a> {
+    settings: &'a Settings,
+    criteria: Vec<&'a Criterion>,
+    offset: Option<String>,
+    done: bool,
+    /// 已读取的页数
+    pages: usize,
+    /// 读取后续页失败时的原因
+    fetch_error: Option<String>,
+}
+
+impl<'a> RowPager<'a> {
+    fn new(settings: &'a Settings, filter: &'a RowFilter) -> Self {
+        let criteria = [
+            settings.dispatch.row_filter.as_ref(),
+            filter.criteria.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .filter_map(RecordFilter::airscript_criteria)
+        .flatten()
+        .collect();
+        RowPager {
+            settings,
+            criteria,
+            offset: None,
+            done: false,
+            pages: 0,
+            fetch_error: None,
+        }
+    }
+
+    /// 读取下一页, 没有更多记录时返回 None. 只有读取第一页失败时返回 Err;
+    /// 后续页读取失败时停止读取, 原因记录在 fetch_error 中, 已读取的行照常处理
+    async fn next_page(&mut self) -> Result<Option<Vec<serde_json::Value>>, String> {
+        if self.done {
+            return Ok(None);
+        }
+        match self.fetch_page().await {
+            Ok(items) => Ok(Some(items)),
+            Err(e) if self.pages == 0 => Err(e),
+            Err(e) => {
+                warn!("读取第{}页WPS数据失败, 停止读取: {}", self.pages + 1, e);
+                self.fetch_error = Some(e);
+                self.done = true;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn fetch_page(&mut self) -> Result<Vec<serde_json::Value>, String> {
+        let page = self.pages + 1;
+        let response = retry::run(
+            &self.settings.retry.wps,
+            || async {
+                wps_reader::fetch_wps_data(
+                    &self.settings.connection,
+                    &self.settings.wps_mapping,
+                    &self.criteria,
+                    self.settings.dispatch.page_size,
+                    self.offset.as_deref(),
+                )
+                .await
+                .map_err(|e| e.classify("数据获取失败"))
+            },
+            |attempt, failure, retrying| {
+                if let Some(failure) = failure {
+                    warn!(
+                        "第{}页第{}次读取WPS数据失败{}: {}",
+                        page,
+                        attempt,
+                        retry_hint(retrying),
+                        failure
+                    );
+                }
+                async {}
+            },
+        )
+        .await?;
+        info!("读取第{}页WPS数据, 共 {} 条记录", page, response.data.len());
+        self.pages = page;
+        self.offset = response.offset.filter(|o| !o.is_empty());
+        self.done = self.offset.is_none() || response.data.is_empty();
+        Ok(response.data)
+    }
+}
+
+/// 按表格映射解析一条记录, 返回待下发的行, 记录格式不对或缺少必要字段时同时返回不能下发的原因
+fn prepare_row(item: &serde_json::Value, mapping: &WpsMapping) -> (RowJob, Option<String>) {
+    let parsed = WpsRecord::parse(item).and_then(|record| {
+        let job = RowJob {
+            sku: record.text(&mapping.sku_column)?.to_string(),
+            module: record.text(&mapping.module_column)?.to_string(),
+            create_time: record
+                .optional_text(&mapping.create_time_column)?
+                .to_string(),
+            row_id: record.id,
+            fields: serde_json::Value::Object(record.fields),
+        };
+        Ok(job)
+    });
+    let job = match parsed {
+        Ok(job) => job,
+        Err(e) => {
+            // 解析失败时尽量带上记录ID与SKU, 便于在表格中找到这一行
+            let str_value = |value: Option<&serde_json::Value>| {
+                value.and_then(|v| v.as_str()).unwrap_or("").to_string()
+            };
+            let fields = item.get("fields");
+            let job = RowJob {
+                row_id: str_value(item.get("id")),
+                sku: str_value(fields.and_then(|f| f.get(&mapping.sku_column))),
+                module: str_value(fields.and_then(|f| f.get(&mapping.module_column))),
+                create_time: String::new(),
+                fields: fields.cloned().unwrap_or_default(),
+            };
+            warn!("记录 {} 无法下发: {}", job.row_id, e);
+            return (job, Some(e));
+        }
+    };
+    if job.sku.is_empty() || job.module.is_empty() {
+        let reason = format!("{} 或 {} 为空", mapping.sku_column, mapping.module_column);
+        warn!("记录 {} 的 {}, 跳过", job.row_id, reason);
+        return (job, Some(reason));
+    }
+    (job, None)
+}
+
+/// 一行记录的筛选结果
+enum Selection {
+    /// 不符合筛选条件
+    Filtered,
+    /// 不能下发的行与原因
+    Skipped(RowJob, String),
+    /// 还需检查该行是否已有任务
+    Candidate(RowJob),
+    /// 已达到每批次最多下发的行数
+    LimitReached,
+}
+
+/// 逐行解析、筛选、去重并限制每批次下发的行数, 试运行与正式执行共用
+struct RowSelector<'a> {
+    settings: &'a Settings,
+    filter: &'a RowFilter,
+    limit: usize,
+    taken: usize,
+    seen: HashSet<String>,
+}
+
+impl<'a> RowSelector<'a> {
+    fn new(settings: &'a Settings, filter: &'a RowFilter) -> Self {
+        RowSelector {
+            settings,
+            filter,
+            limit: settings.dispatch.max_rows_per_run.unwrap_or(usize::MAX),
+            taken: 0,
+            seen: HashSet::new(),
+        }
+    }
+
+    fn select(&mut self, item: &serde_json::Value) -> Selection {
+        if self.taken >= self.limit {
+            return Selection::LimitReached;
+        }
+        let (job, problem) = prepare_row(item, &self.settings.wps_mapping);
+        if let Some(reason) = problem {
+            return Selection::Skipped(job, reason);
+        }
+        let selected = self
+            .settings
+            .dispatch
+            .row_filter
+            .as_ref()
+            .is_none_or(|f| f.matches(&job.fields))
+            && self.filter.matches(&job.sku, &job.module, &job.fields);
+        if !selected {
+            return Selection::Filtered;
+        }
+        // 同一批次中重复出现的行只下发第一条, 强制重新下发时也一样
+        if !job.row_id.is_empty() && !self.seen.insert(job.row_id.clone()) {
+            return Selection::Skipped(job, "同一批次中重复出现".to_string());
+        }
+        Selection::Candidate(job)
+    }
+
+    /// 该行将被下发, 计入每批次最多下发的行数
+    fn take(&mut self) {
+        self.taken += 1;
+    }
+}
+
+/// 试运行: 按与正式执行相同的规则读取、筛选、校验每一行, 返回将要下发的内容与不能下发的原因.
+/// 只读取数据库, 不创建任务
+pub async fn plan_batch(app: &AppHandle, options: &BatchOptions) -> Result<BatchPlan, String> {
+    let settings = app.state::<SettingsState>().checked()?;
+    let mut pager = RowPager::new(&settings, &options.filter);
+    let mut selector = RowSelector::new(&settings, &options.filter);
+    let db: tauri::State<Db> = app.state();
+    let mut plan = BatchPlan::default();
+    let mut total = 0;
+    'pages: while let Some(items) = pager.next_page().await? {
+        total += items.len();
+        let conn = db.0.lock().await;
+        for item in &items {
+            let (job, problems) = match selector.select(item) {
+                Selection::LimitReached => {
+                    plan.limit_reached = true;
+                    break 'pages;
+                }
+                Selection::Filtered => {
+                    plan.filtered += 1;
+                    continue;
+                }
+                Selection::Skipped(job, reason) => (job, vec![reason]),
+                Selection::Candidate(job) => {
+                    let existing = tasks::find_active_task(&conn, &job.row_id, !options.rerun)
+                        .map_err(|e| format!("数据库查询失败: {}", e))?;
+                    match existing {
+                        Some(existing) => {
+                            let reason = format!("已有执行中或已成功的任务 {}", existing);
+                            (job, vec![reason])
+                        }
+                        None => {
+                            selector.take();
+                            plan.dispatchable += 1;
+                            (job, Vec::new())
+                        }
+                    }
+                }
+            };
+            plan.rows.push(PlannedRow {
+                row_id: job.row_id,
+                sku: job.sku,
+                module: job.module,
+                payload: job.fields,
+                problems,
+            });
+        }
+    }
+    plan.pages = pager.pages;
+    plan.fetch_error = pager.fetch_error;
+    info!(
+        "试运行: 共 {} 条记录, 可下发 {} 条, 不符合筛选条件 {} 条",
+        total, plan.dispatchable, plan.filtered
+    );
+    Ok(plan)
+}
+
+/// 分页拉取 WPS 数据并按并发设置下发给 automator, 读取一页即开始下发, 每行的结果记录在 tasks 与 task_logs 中.
+/// 只有读取第一页失败时返回 Err, 后续页读取失败时已读取的行照常执行; 单行的错误记录在该行的结果中
+pub async fn run_batch(
+    app: &AppHandle,
+    options: &BatchOptions,
+    batch: &BatchGuard,
+) -> Result<BatchSummary, String> {
+    let settings = app.state::<SettingsState>().checked()?;
+    let mut pager = RowPager::new(&settings, &options.filter);
+    let mut selector = RowSelector::new(&settings, &options.filter);
+    let db: tauri::State<Db> = app.state();
+    let mut dispatcher = Dispatcher::new(app, batch, &settings);
+    let mut summary = BatchSummary::default();
+
+    'pages: while !batch.token.is_cancelled() {
+        let Some(items) = pager.next_page().await? else {
+            break;
+        };
+        for item in &items {
+            // 取消后本页剩余的行不再创建任务, 下次执行时仍会被选中
+            if batch.token.is_cancelled() {
+                break 'pages;
+            }
+            let job = match selector.select(item) {
+                Selection::LimitReached => {
+                    summary.limit_reached = true;
+                    break 'pages;
+                }
+                Selection::Filtered => {
+                    summary.filtered += 1;
+                    continue;
+                }
+                Selection::Skipped(job, reason) => {
+                    summary.record(job.result(None, TaskState::Skipped, Some(reason)));
+                    continue;
+                }
+                Selection::Candidate(job) => job,
+            };
+            // 排队时即创建任务, 并行批次中重复出现的行也能识别出来
+            match enqueue_row(&db, &job, options.rerun, None).await {
+                Ok(task_id) => {
+                    selector.take();
+                    dispatcher.spawn(job, task_id);
+                }
+                Err(row) => summary.record(row),
+            }
+        }
+    }
+    summary.pages = pager.pages;
+    summary.fetch_error = pager.fetch_error;
+    dispatcher.finish(&mut summary).await;
+    info!("{}", summary.message());
+    Ok(summary)
+}
+
+/// 以保存的下发内容重新执行已结束的任务. 每个任务创建一个指向原任务的新任务,
+/// 该行已有排队中、执行中或已成功的任务时跳过
+pub async fn rerun_tasks(
+    app: &AppHandle,
+    task_ids: &[String],
+    batch: &BatchGuard,
+) -> Result<BatchSummary, String> {
+    let settings = app.state::<SettingsState>().checked()?;
+    let db: tauri::State<Db> = app.state();
+    let mut dispatcher = Dispatcher::new(app, batch, &settings);
+    let mut summary = BatchSummary::default();
+
+    for parent in task_ids {
+        let source = {
+            let conn = db.0.lock().await;
+            tasks::rerun_source(&conn, parent)
+        };
+        let job = match source {
+            Ok(source) => RowJob {
+                row_id: source.row_id,
+                sku: source.sku,
+                module: source.module,
+                create_time: source.create_time,
+                fields: source.payload,
+            },
+            Err(reason) => {
+                summary.record(RowResult {
+                    row_id: String::new(),
+                    sku: String::new(),
+                    module: String::new(),
+                    task_id: None,
+                    state: TaskState::Skipped,
+                    reason: Some(reason),
+                });
+                continue;
+            }
+        };
+        match enqueue_row(&db, &job, false, Some(parent)).await {
+            Ok(task_id) => {
+                log_task(&db, &task_id, format!("重新执行任务 {}", parent)).await;
+                dispatcher.spawn(job, task_id);
+            }
+            Err(row) => summary.record(row),
+        }
+    }
+    dispatcher.finish(&mut summary).await;
+    info!("重新执行: {}", summary.message());
+    Ok(summary)
+}
+
+/// 执行任务共用的请求客户端与设置
+struct RowContext {
+    client: reqwest::Client,
+    connection: ConnectionSettings,
+    retry: RetrySettings,
+    mapping: WpsMapping,
+}
+
+/// 按并发设置执行批次中排队的任务
+struct Dispatcher<'a> {
+    app: &'a AppHandle,
+    batch: &'a BatchGuard,
+    dispatch: DispatchSettings,
+    context: Arc<RowContext>,
+    global: Arc<Semaphore>,
+    module_semaphores: HashMap<String, Arc<Semaphore>>,
+    set: JoinSet<RowResult>,
+    // 任务异常退出时用于找回是哪一行
+    spawned: HashMap<tokio::task::Id, RowResult>,
+}
+
+impl<'a> Dispatcher<'a> {
+    fn new(app: &'a AppHandle, batch: &'a BatchGuard, settings: &Settings) -> Self {
+        Dispatcher {
+            app,
+            batch,
+            global: Arc::new(Semaphore::new(settings.dispatch.max_concurrency)),
+            dispatch: settings.dispatch.clone(),
+            context: Arc::new(RowContext {
+                client: reqwest::Client::new(),
+                connection: settings.connection.clone(),
+                retry: settings.retry.clone(),
+                mapping: settings.wps_mapping.clone(),
+            }),
+            module_semaphores: HashMap::new(),
+            set: JoinSet::new(),
+            spawned: HashMap::new(),
+        }
+    }
+
+    /// 执行已创建的排队中任务
+    fn spawn(&mut self, job: RowJob, task_id: String) {
+        let module_semaphore = self.dispatch.module_limits.get(&job.module).map(|limit| {
+            self.module_semaphores
+                .entry(job.module.clone())
+                .or_insert_with(|| Arc::new(Semaphore::new(*limit)))
+                .clone()
+        });
+        let timeout = self.dispatch.timeout_for(&job.module);
+        let placeholder = job.result(Some(task_id.clone()), TaskState::Failed, None);
+        let task = self.batch.register_task(&task_id);
+        let global = self.global.clone();
+        let context = self.context.clone();
+        let app = self.app.clone();
+        let handle = self.set.spawn(async move {
+            // 先按模版排队, 避免等待中的行占用总并发名额. 排队期间被取消时不再等待
+            let _permits = task
+                .token
+                .or_cancelled(async {
+                    let module_permit = match module_semaphore {
+                        Some(semaphore) => Some(semaphore.acquire_owned().await),
+                        None => None,
+                    };
+                    (module_permit, global.acquire_owned().await)
+                })
+                .await;
+            let db: tauri::State<Db> = app.state();
+            execute_row(&db, &context, timeout, &job, task_id, &task.token).await
+        });
+        self.spawned.insert(handle.id(), placeholder);
+    }
+
+    /// 等待所有任务结束并记录结果
+    async fn finish(mut self, summary: &mut BatchSummary) {
+        while let Some(joined) = self.set.join_next_with_id().await {
+            match joined {
+                Ok((_, row)) => summary.record(row),
+                Err(e) => {
+                    if let Some(mut row) = self.spawned.remove(&e.id()) {
+                        row.reason = Some(format!("任务执行异常: {}", e));
+                        summary.record(row);
+                    }
+                }
+            }
+        }
+        summary.cancelled = self.batch.token.is_cancelled();
+    }
+}
+
+fn retry_hint(retrying: bool) -> &'static str {
+    if retrying {
+        ", 稍后重试"
+    } else {
+        ""
+    }
+}
+
+async fn log_task(db: &Db, task_id: &str, message: String) {
+    let conn = db.0.lock().await;
+    if let Err(e) = conn.execute(
+        "INSERT INTO task_logs (task_id, log_time, message) VALUES (?1, ?2, ?3)",
+        params![task_id, chrono::Local::now().to_rfc3339(), message],
+    ) {
+        warn!("日志插入失败: {}", e);
+    }
+}
+
+/// 带重试地执行一次请求, 每次尝试都记录到该任务的日志中
+async fn with_retry<T, F, Fut>(
+    db: &Db,
+    task_id: &str,
+    policy: &RetryPolicy,
+    action: &str,
+    op: F,
+) -> Result<T, Failure>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, Failure>>,
+{
+    retry::run(policy, op, |attempt, failure, retrying| {
+        let message = match failure {
+            None => format!("第{}次{}成功", attempt, action),
+            Some(failure) => format!(
+                "第{}次{}失败{}: {}",
+                attempt,
+                action,
+                retry_hint(retrying),
+                failure
+            ),
+        };
+        log_task(db, task_id, message)
+    })
+    .await
+}
+
+/// 请求 automator, 拒绝连接与 5xx 视为临时性失败. 超过 timeout 仍没有结果时不重试,
+/// 避免卡住的 Photoshop 被重复下发. 每次请求的时间、HTTP 状态与原始响应记录在任务中
+async fn post_automator(
+    db: &Db,
+    context: &RowContext,
+    timeout: Duration,
+    task_id: &str,
+    fields: &serde_json::Value,
+) -> Result<AutomatorResponse, Failure> {
+    if let Err(e) = tasks::record_request(&*db.0.lock().await, task_id) {
+        warn!("记录请求时间失败: {}", e);
+    }
+    // 带上任务ID, 支持取消的 automator 可以据此找到对应的任务
+    let resp = context
+        .client
+        .post(context.connection.automator_endpoint("automator"))
+        .header("X-Task-Id", task_id)
+        .json(fields)
+        .timeout(timeout)
+        .send()
+        .await
+        .map_err(|e| automator_failure(timeout, &e))?;
+    let status = resp.status();
+    let body = match resp.text().await {
+        Ok(body) => body,
+        Err(e) if e.is_timeout() => return Err(automator_failure(timeout, &e)),
+        Err(_) => String::new(),
+    };
+    if let Err(e) = tasks::record_response(&*db.0.lock().await, task_id, status.as_u16(), &body) {
+        warn!("记录后台服务响应失败: {}", e);
+    }
+    if status.is_server_error() {
+        return Err(Failure::Transient(format!(
+            "后台服务返回错误状态: {}",
+            status
+        )));
+    }
+    serde_json::from_str(&body)
+        .map_err(|e| Failure::Permanent(format!("后台服务响应格式不正确: {}", e)))
+}
+
+fn automator_failure(timeout: Duration, e: &reqwest::Error) -> Failure {
+    if e.is_timeout() {
+        Failure::TimedOut(format!("后台服务在 {} 秒内没有返回结果", timeout.as_secs()))
+    } else {
+        retry::classify("请求后台服务失败", e)
+    }
+}
+
+/// 回写执行结果, 回写的列与值由表格映射决定
+async fn update_wps(
+    db: &Db,
+    context: &RowContext,
+    task_id: &str,
+    row_id: &str,
+    success: bool,
+) -> Result<(), String> {
+    with_retry(db, task_id, &context.retry.wps, "回写WPS", || async {
+        wps_reader::update_wps_date(&context.connection, &context.mapping, row_id, success)
+            .await
+            .map_err(|e| e.classify("更新任务状态失败"))
+    })
+    .await?;
+    Ok(())
+}
+
+/// 为该行创建排队中的任务. 是否重复的判断与插入任务在同一次加锁内完成,
+/// 并行批次中重复出现的行只会下发一次. rerun 为 true 时已成功的行也会下发
+async fn enqueue_row(
+    db: &Db,
+    job: &RowJob,
+    rerun: bool,
+    parent_task_id: Option<&str>,
+) -> Result<String, RowResult> {
+    let conn = db.0.lock().await;
+    match tasks::find_active_task(&conn, &job.row_id, !rerun) {
+        Ok(Some(existing)) => {
+            info!(
+                "记录 {} (SKU {}) 已有任务 {}, 不再重复下发",
+                job.row_id, job.sku, existing
+            );
+            return Err(job.result(
+                None,
+                TaskState::Skipped,
+                Some(format!("已有执行中或已成功的任务 {}", existing)),
+            ));
+        }
+        Ok(None) => {}
+        Err(e) => {
+            return Err(job.result(
+                None,
+                TaskState::Failed,
+                Some(format!("数据库查询失败: {}", e)),
+            ))
+        }
+    }
+    let task_id = Uuid::now_v7().to_string();
+    let task = tasks::NewTask {
+        task_id: &task_id,
+        row_id: &job.row_id,
+        sku: &job.sku,
+        module: &job.module,
+        create_time: &job.create_time,
+        payload: &job.fields,
+        parent_task_id,
+    };
+    tasks::insert_task(&conn, &task).map_err(|e| {
+        job.result(
+            None,
+            TaskState::Failed,
+            Some(format!("数据库插入失败: {}", e)),
+        )
+    })?;
+    Ok(task_id)
+}
+
+/// 修改任务状态, 状态机不允许时记录警告(不影响该行的处理结果)
+async fn set_state(db: &Db, task_id: &str, state: TaskState) -> Result<(), String> {
+    let conn = db.0.lock().await;
+    tasks::transition(&conn, task_id, state).inspect_err(|e| warn!("{}", e))
+}
+
+/// 取得并发名额后执行排队中的任务, 已取消的任务不再下发
+async fn execute_row(
+    db: &Db,
+    context: &RowContext,
+    timeout: Duration,
+    job: &RowJob,
+    task_id: String,
+    cancel: &CancelToken,
+) -> RowResult {
+    if cancel.is_cancelled() {
+        let _ = set_state(db, &task_id, TaskState::Cancelled).await;
+        log_task(db, &task_id, "任务已取消, 未下发".to_string()).await;
+        return job.result(
+            Some(task_id),
+            TaskState::Cancelled,
+            Some("任务已取消".to_string()),
+        );
+    }
+    if let Err(e) = set_state(db, &task_id, TaskState::Running).await {
+        return job.result(Some(task_id), TaskState::Failed, Some(e));
+    }
+    let (state, reason) = run_row(db, context, timeout, job, &task_id, cancel).await;
+    match (state, &reason) {
+        (TaskState::Failed, Some(reason)) => {
+            log_task(db, &task_id, format!("任务执行失败: {}", reason)).await
+        }
+        (TaskState::TimedOut, Some(reason)) => {
+            log_task(db, &task_id, format!("任务执行超时: {}", reason)).await
+        }
+        (TaskState::Cancelled, _) => {
+            log_task(db, &task_id, "任务已取消, 未回写WPS".to_string()).await
+        }
+        _ => {}
+    }
+    let _ = set_state(db, &task_id, state).await;
+    job.result(Some(task_id), state, reason)
+}
+
+/// 等待 automator 可以连接. 超过 max_wait 仍无法连接时不再等待, 由重试策略处理
+pub async fn wait_for_automator(app: &AppHandle, max_wait: Duration) {
+    let url = app.state::<SettingsState>().get().connection.automator_url;
+    let client = reqwest::Client::new();
+    let deadline = tokio::time::Instant::now() + max_wait;
+    let mut logged = false;
+    loop {
+        match client
+            .get(&url)
+            .timeout(Duration::from_secs(2))
+            .send()
+            .await
+        {
+            // 只要有响应(包括 404)就说明服务已经启动
+            Ok(_) => return,
+            Err(e) if !e.is_connect() && !e.is_timeout() => return,
+            Err(e) => {
+                if tokio::time::Instant::now() >= deadline {
+                    warn!("等待后台服务启动超时, 照常执行: {}", e);
+                    return;
+                }
+                if !logged {
+                    info!("后台服务尚未启动, 等待后再执行: {}", e);
+                    logged = true;
+                }
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// 通知 automator 取消任务. 尽力而为, automator 不支持时只记录日志
+async fn cancel_automator(db: &Db, context: &RowContext, task_id: &str) {
+    let result = context
+        .client
+        .post(context.connection.automator_endpoint("cancel"))
+        .json(&serde_json::json!({ "task_id": task_id }))
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await;
+    let message = match result {
+        Ok(resp) if resp.status() == reqwest::StatusCode::NOT_FOUND => {
+            "后台服务不支持取消, 已停止等待结果".to_string()
+        }
+        Ok(resp) if resp.status().is_success() => "已通知后台服务取消任务".to_string(),
+        Ok(resp) => format!("通知后台服务取消任务失败: {}", resp.status()),
+        Err(e) => format!("通知后台服务取消任务失败: {}", e),
+    };
+    log_task(db, task_id, message).await;
+}
+
+/// 下发一行并回写 WPS, 返回任务的最终状态与原因.
+/// automator 执行成功但回写失败时仍视为成功(避免重复渲染), 原因中记录回写失败.
+/// 等待 automator 期间被取消时通知 automator 取消, 不回写 WPS, 该行在下次执行时仍会被选中.
+/// 超时时同样通知 automator 取消, 并回写为未完成
+async fn run_row(
+    db: &Db,
+    context: &RowContext,
+    timeout: Duration,
+    job: &RowJob,
+    task_id: &str,
+    cancel: &CancelToken,
+) -> (TaskState, Option<String>) {
+    let posted = cancel
+        .or_cancelled(with_retry(
+            db,
+            task_id,
+            &context.retry.automator,
+            "请求后台服务",
+            || post_automator(db, context, timeout, task_id, &job.fields),
+        ))
+        .await;
+    let response = match posted {
+        Some(Ok(response)) => response,
+        Some(Err(Failure::TimedOut(message))) => {
+            cancel_automator(db, context, task_id).await;
+            let _ = update_wps(db, context, task_id, &job.row_id, false).await;
+            return (TaskState::TimedOut, Some(message));
+        }
+        Some(Err(failure)) => return (TaskState::Failed, Some(failure.to_string())),
+        None => {
+            cancel_automator(db, context, task_id).await;
+            return (TaskState::Cancelled, Some("任务已取消".to_string()));
+        }
+    };
+    if let Some(message) = response.error() {
+        // 回写失败已记录在任务日志中, 以 automator 的错误为准
+        let _ = update_wps(db, context, task_id, &job.row_id, false).await;
+        return (TaskState::Failed, Some(message.to_string()));
+    }
+    if !response.logs.is_empty() {
+        let conn = db.0.lock().await;
+        for log in &response.logs {
+            let (log_time, message) = match log {
+                AutomatorLog::Entry(log_time, message) => (log_time.clone(), message.clone()),
+                AutomatorLog::Malformed(raw) => {
+                    warn!("任务 {} 的日志格式不正确: {}", task_id, raw);
+                    let now = chrono::Local::now().to_rfc3339();
+                    (now, format!("日志格式不正确: {}", raw))
+                }
+            };
+            if let Err(e) = conn.execute(
+                "INSERT INTO task_logs (task_id, log_time, message) VALUES (?1, ?2, ?3)",
+                params![task_id, log_time, message],
+            ) {
+                warn!("日志插入失败: {}", e);
+            }
+        }
+    }
+    let warning = update_wps(db, context, task_id, &job.row_id, true)
+        .await
+        .err()
+        .map(|e| format!("回写WPS失败: {}", e));
+    (TaskState::Succeeded, warning)
+}
@@ -0,0 +1,134 @@
+use chrono::{DateTime, Local};
+use log::warn;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::dispatch::BatchSummary;
+use crate::scheduler::ScheduleRecord;
+
+/// 定时循环已启动(或按新配置重新启动)
+pub const ARMED: &str = "scheduler:armed";
+/// 下次触发时间发生变化
+pub const NEXT_FIRE_AT: &str = "scheduler:next-fire-at";
+pub const TICK_STARTED: &str = "scheduler:tick-started";
+/// 批次执行结束, 载荷中带执行结果摘要
+pub const TICK_FINISHED: &str = "scheduler:tick-finished";
+/// 本次触发未执行: 上一批次未结束、命中屏蔽日历或按策略忽略错过的触发
+pub const TICK_SKIPPED: &str = "scheduler:tick-skipped";
+/// 定时循环已结束: 手动停止、删除或没有后续触发
+pub const STOPPED: &str = "scheduler:stopped";
+
+/// 每个定时任务(含手动执行)的当前状态, 也是所有调度事件的载荷
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScheduleStatus {
+    pub schedule_id: String,
+    pub name: String,
+    pub trigger: String,
+    /// 定时循环是否在运行
+    pub armed: bool,
+    /// 是否有批次正在执行
+    pub running: bool,
+    pub next_fire_at: Option<String>,
+    pub last_started_at: Option<String>,
+    pub last_finished_at: Option<String>,
+    pub last_success: Option<bool>,
+    pub last_summary: Option<String>,
+    pub last_skipped_at: Option<String>,
+    pub last_skip_reason: Option<String>,
+    pub stop_reason: Option<String>,
+}
+
+/// 调度状态, 由定时循环更新, 供 get_scheduler_status 查询
+#[derive(Default)]
+pub struct SchedulerStatus(Mutex<HashMap<String, ScheduleStatus>>);
+
+impl SchedulerStatus {
+    pub fn snapshot(&self) -> Vec<ScheduleStatus> {
+        let mut items: Vec<ScheduleStatus> = self.0.lock().unwrap().values().cloned().collect();
+        items.sort_by(|a, b| a.schedule_id.cmp(&b.schedule_id));
+        items
+    }
+}
+
+fn format_time(t: &DateTime<Local>) -> String {
+    t.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// 更新状态并把更新后的完整状态发送给所有窗口
+fn update(app: &AppHandle, event: &str, schedule_id: &str, f: impl FnOnce(&mut ScheduleStatus)) {
+    let status = {
+        let state: tauri::State<SchedulerStatus> = app.state();
+        let mut map = state.0.lock().unwrap();
+        let status = map
+            .entry(schedule_id.to_string())
+            .or_insert_with(|| ScheduleStatus {
+                schedule_id: schedule_id.to_string(),
+                ..Default::default()
+            });
+        f(status);
+        status.clone()
+    };
+    if let Err(e) = app.emit(event, &status) {
+        warn!("发送调度事件失败: {} {}", event, e);
+    }
+}
+
+pub fn armed(app: &AppHandle, record: &ScheduleRecord) {
+    update(app, ARMED, &record.id, |s| {
+        s.name = record.name.clone();
+        s.trigger = record.trigger.describe();
+        s.armed = true;
+        s.next_fire_at = None;
+        s.stop_reason = None;
+    });
+}
+
+pub fn next_fire_at(app: &AppHandle, schedule_id: &str, at: &DateTime<Local>) {
+    update(app, NEXT_FIRE_AT, schedule_id, |s| {
+        s.next_fire_at = Some(format_time(at));
+    });
+}
+
+pub fn tick_started(app: &AppHandle, schedule_id: &str) {
+    update(app, TICK_STARTED, schedule_id, |s| {
+        s.running = true;
+        s.last_started_at = Some(format_time(&Local::now()));
+    });
+}
+
+/// 读取数据失败或有任意一行失败时, last_success 为 false
+pub fn tick_finished(app: &AppHandle, schedule_id: &str, result: &Result<BatchSummary, String>) {
+    update(app, TICK_FINISHED, schedule_id, |s| {
+        s.running = false;
+        s.last_finished_at = Some(format_time(&Local::now()));
+        s.last_success = Some(result.as_ref().is_ok_and(|summary| summary.failed == 0));
+        s.last_summary = Some(match result {
+            Ok(summary) => summary.message(),
+            Err(e) => e.clone(),
+        });
+    });
+}
+
+pub fn tick_skipped(app: &AppHandle, schedule_id: &str, reason: &str) {
+    update(app, TICK_SKIPPED, schedule_id, |s| {
+        s.last_skipped_at = Some(format_time(&Local::now()));
+        s.last_skip_reason = Some(reason.to_string());
+    });
+}
+
+pub fn stopped(app: &AppHandle, schedule_id: &str, reason: &str) {
+    update(app, STOPPED, schedule_id, |s| {
+        s.armed = false;
+        s.next_fire_at = None;
+        s.stop_reason = Some(reason.to_string());
+    });
+}
+
+/// 删除定时任务时一并移除状态, 移除前发送 stopped 事件
+pub fn removed(app: &AppHandle, schedule_id: &str) {
+    stopped(app, schedule_id, "定时任务已删除");
+    let state: tauri::State<SchedulerStatus> = app.state();
+    state.0.lock().unwrap().remove(schedule_id);
+}
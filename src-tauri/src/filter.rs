@@ -0,0 +1,365 @@
+use chrono::{Local, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// 与 WPS Airscript 的 filter 参数结构一致: { mode, criteria: [{ field, op, values }] }.
+/// criteria 中可以嵌套条件组, 嵌套的条件组只在本地判断
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordFilter {
+    pub mode: FilterMode,
+    pub criteria: Vec<Condition>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum FilterMode {
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Condition {
+    Criterion(Criterion),
+    Group(RecordFilter),
+}
+
+/// 单个条件, values 中任意一个满足即可(不等于、不包含为全部不满足)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Criterion {
+    pub field: String,
+    pub op: FilterOp,
+    #[serde(default)]
+    pub values: Vec<String>,
+}
+
+/// 条件运算, 名称与 Airscript 一致. 大小比较依次按数字、日期、文本比较, 日期可以写 today
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FilterOp {
+    Equals,
+    #[serde(rename = "NotEqu", alias = "NotEquals")]
+    NotEquals,
+    Contains,
+    NotContains,
+    BeginWith,
+    EndWith,
+    Greater,
+    GreaterEqu,
+    Less,
+    LessEqu,
+    Empty,
+    NotEmpty,
+}
+
+impl FilterOp {
+    fn needs_values(&self) -> bool {
+        !matches!(self, FilterOp::Empty | FilterOp::NotEmpty)
+    }
+
+    /// 文本运算在 Airscript 与本地的结果一致, 可以交给 Airscript 筛选
+    fn is_text(&self) -> bool {
+        !matches!(
+            self,
+            FilterOp::Greater | FilterOp::GreaterEqu | FilterOp::Less | FilterOp::LessEqu
+        )
+    }
+}
+
+impl RecordFilter {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.criteria.is_empty() {
+            return Err("筛选条件组不能为空".to_string());
+        }
+        for condition in &self.criteria {
+            match condition {
+                Condition::Criterion(criterion) => criterion.validate()?,
+                Condition::Group(group) => group.validate()?,
+            }
+        }
+        Ok(())
+    }
+
+    /// 判断一行是否满足条件, fields 为 WPS 记录的 fields
+    pub fn matches(&self, fields: &serde_json::Value) -> bool {
+        let mut results = self.criteria.iter().map(|condition| match condition {
+            Condition::Criterion(criterion) => criterion.matches(fields),
+            Condition::Group(group) => group.matches(fields),
+        });
+        match self.mode {
+            FilterMode::And => results.all(|matched| matched),
+            FilterMode::Or => results.any(|matched| matched),
+        }
+    }
+
+    /// 可以直接附加到 Airscript 查询条件中的部分: 只含文本运算、没有嵌套的 AND 条件组.
+    /// 其余条件只在本地判断
+    pub fn airscript_criteria(&self) -> Option<Vec<&Criterion>> {
+        if self.mode != FilterMode::And {
+            return None;
+        }
+        self.criteria
+            .iter()
+            .map(|condition| match condition {
+                Condition::Criterion(criterion) if criterion.op.is_text() => Some(criterion),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl Criterion {
+    fn validate(&self) -> Result<(), String> {
+        if self.field.trim().is_empty() {
+            return Err("筛选条件的列名不能为空".to_string());
+        }
+        if self.op.needs_values() && self.values.is_empty() {
+            return Err(format!("列 {} 的筛选条件缺少比较值", self.field));
+        }
+        Ok(())
+    }
+
+    fn matches(&self, fields: &serde_json::Value) -> bool {
+        let cells = cell_texts(fields.get(&self.field));
+        let any = |f: &dyn Fn(&str, &str) -> bool| {
+            cells
+                .iter()
+                .any(|cell| self.values.iter().any(|value| f(cell, value)))
+        };
+        match self.op {
+            FilterOp::Equals => any(&|cell, value| cell == value),
+            FilterOp::NotEquals => !any(&|cell, value| cell == value),
+            FilterOp::Contains => any(&|cell, value| cell.contains(value)),
+            FilterOp::NotContains => !any(&|cell, value| cell.contains(value)),
+            FilterOp::BeginWith => any(&|cell, value| cell.starts_with(value)),
+            FilterOp::EndWith => any(&|cell, value| cell.ends_with(value)),
+            FilterOp::Empty => cells.iter().all(|cell| cell.is_empty()),
+            FilterOp::NotEmpty => cells.iter().any(|cell| !cell.is_empty()),
+            FilterOp::Greater => any(&|cell, value| compare(cell, value).is_gt()),
+            FilterOp::GreaterEqu => any(&|cell, value| compare(cell, value).is_ge()),
+            FilterOp::Less => any(&|cell, value| compare(cell, value).is_lt()),
+            FilterOp::LessEqu => any(&|cell, value| compare(cell, value).is_le()),
+        }
+    }
+}
+
+/// 单元格的文本, 多选等数组类型的单元格每一项单独比较
+fn cell_texts(value: Option<&serde_json::Value>) -> Vec<String> {
+    match value {
+        None | Some(serde_json::Value::Null) => Vec::new(),
+        Some(serde_json::Value::String(text)) => vec![text.clone()],
+        Some(serde_json::Value::Array(items)) => items
+            .iter()
+            .flat_map(|item| cell_texts(Some(item)))
+            .collect(),
+        Some(other) => vec![other.to_string()],
+    }
+}
+
+fn compare(cell: &str, value: &str) -> Ordering {
+    if let (Ok(a), Ok(b)) = (cell.trim().parse::<f64>(), value.trim().parse::<f64>()) {
+        return a.partial_cmp(&b).unwrap_or(Ordering::Equal);
+    }
+    if let (Some(a), Some(b)) = (parse_date(cell), parse_date(value)) {
+        return a.cmp(&b);
+    }
+    cell.cmp(value)
+}
+
+fn parse_date(text: &str) -> Option<NaiveDateTime> {
+    let text = text.trim();
+    if text.eq_ignore_ascii_case("today") {
+        return Local::now().date_naive().and_hms_opt(0, 0, 0);
+    }
+    const DATE_TIME_FORMATS: [&str; 4] = [
+        "%Y-%m-%d %H:%M:%S",
+        "%Y/%m/%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y/%m/%d %H:%M",
+    ];
+    DATE_TIME_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .or_else(|| {
+            ["%Y-%m-%d", "%Y/%m/%d"]
+                .iter()
+                .find_map(|format| NaiveDate::parse_from_str(text, format).ok())
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn criterion(field: &str, op: FilterOp, values: &[&str]) -> Criterion {
+        Criterion {
+            field: field.to_string(),
+            op,
+            values: values.iter().map(|v| v.to_string()).collect(),
+        }
+    }
+
+    fn filter(mode: FilterMode, criteria: Vec<Condition>) -> RecordFilter {
+        RecordFilter { mode, criteria }
+    }
+
+    #[test]
+    fn deserializes_airscript_filter() {
+        let filter: RecordFilter = serde_json::from_value(json!({
+            "mode": "OR",
+            "criteria": [
+                {"field": "模版", "op": "NotEqu", "values": ["A"]},
+                {"mode": "AND", "criteria": [{"field": "SKU", "op": "Empty"}]}
+            ]
+        }))
+        .unwrap();
+        assert_eq!(filter.mode, FilterMode::Or);
+        assert!(matches!(
+            &filter.criteria[0],
+            Condition::Criterion(Criterion {
+                op: FilterOp::NotEquals,
+                ..
+            })
+        ));
+        assert!(matches!(&filter.criteria[1], Condition::Group(_)));
+        assert!(filter.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_empty_groups_and_missing_values() {
+        assert!(filter(FilterMode::And, Vec::new()).validate().is_err());
+        let missing = filter(
+            FilterMode::And,
+            vec![Condition::Criterion(criterion(
+                "SKU",
+                FilterOp::Equals,
+                &[],
+            ))],
+        );
+        assert!(missing.validate().is_err());
+        let blank = filter(
+            FilterMode::And,
+            vec![Condition::Criterion(criterion(" ", FilterOp::Empty, &[]))],
+        );
+        assert!(blank.validate().is_err());
+    }
+
+    #[test]
+    fn text_ops_match_any_value() {
+        let fields = json!({"SKU": "AB-123"});
+        assert!(criterion("SKU", FilterOp::Equals, &["X", "AB-123"]).matches(&fields));
+        assert!(criterion("SKU", FilterOp::Contains, &["B-1"]).matches(&fields));
+        assert!(criterion("SKU", FilterOp::BeginWith, &["AB"]).matches(&fields));
+        assert!(criterion("SKU", FilterOp::EndWith, &["123"]).matches(&fields));
+        assert!(!criterion("SKU", FilterOp::NotEquals, &["X", "AB-123"]).matches(&fields));
+        assert!(!criterion("SKU", FilterOp::NotContains, &["X", "AB"]).matches(&fields));
+        assert!(criterion("SKU", FilterOp::NotContains, &["X", "Y"]).matches(&fields));
+    }
+
+    #[test]
+    fn array_cells_compare_each_item() {
+        let fields = json!({"标签": ["红", "蓝"]});
+        assert!(criterion("标签", FilterOp::Equals, &["蓝"]).matches(&fields));
+        assert!(!criterion("标签", FilterOp::NotEquals, &["蓝"]).matches(&fields));
+        assert!(criterion("标签", FilterOp::NotEmpty, &[]).matches(&fields));
+        assert!(criterion("标签", FilterOp::Empty, &[]).matches(&json!({"标签": []})));
+    }
+
+    #[test]
+    fn missing_cells() {
+        let fields = json!({"SKU": null});
+        assert!(criterion("SKU", FilterOp::Empty, &[]).matches(&fields));
+        assert!(criterion("模版", FilterOp::Empty, &[]).matches(&fields));
+        assert!(criterion("SKU", FilterOp::Empty, &[]).matches(&json!({"SKU": ""})));
+        assert!(!criterion("模版", FilterOp::NotEmpty, &[]).matches(&fields));
+        assert!(!criterion("模版", FilterOp::Equals, &["A"]).matches(&fields));
+        // 没有任何值等于/包含比较值
+        assert!(criterion("模版", FilterOp::NotEquals, &["A"]).matches(&fields));
+        assert!(criterion("模版", FilterOp::NotContains, &["A"]).matches(&fields));
+    }
+
+    #[test]
+    fn compare_numbers_before_text() {
+        assert_eq!(compare("10", "9"), Ordering::Greater);
+        assert_eq!(compare(" 2.50", "2.5"), Ordering::Equal);
+        assert!(criterion("数量", FilterOp::Greater, &["9"]).matches(&json!({"数量": 10})));
+        assert!(criterion("数量", FilterOp::LessEqu, &["10"]).matches(&json!({"数量": "10"})));
+    }
+
+    #[test]
+    fn compare_dates_in_supported_formats() {
+        assert_eq!(compare("2026/06/10", "2026-06-09 23:59"), Ordering::Greater);
+        assert_eq!(
+            compare("2026-06-10 00:00:00", "2026/06/10"),
+            Ordering::Equal
+        );
+        let today = Local::now().date_naive();
+        let yesterday = (today - chrono::Duration::days(1))
+            .format("%Y-%m-%d")
+            .to_string();
+        let later = today.format("%Y-%m-%d 12:00").to_string();
+        assert_eq!(compare(&yesterday, "today"), Ordering::Less);
+        assert_eq!(compare(&later, "TODAY"), Ordering::Greater);
+        assert_eq!(
+            compare(&today.format("%Y/%m/%d").to_string(), "today"),
+            Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn compare_falls_back_to_text() {
+        assert_eq!(compare("b", "a"), Ordering::Greater);
+        // 不是日期也不是数字时按文本比较
+        assert_eq!(compare("10", "abc"), Ordering::Less);
+        assert_eq!(compare("2026-13-01", "2026-12-01"), Ordering::Greater);
+    }
+
+    #[test]
+    fn groups_combine_with_mode() {
+        let fields = json!({"SKU": "A1", "模版": "主图"});
+        let sku = Condition::Criterion(criterion("SKU", FilterOp::Equals, &["A1"]));
+        let other = Condition::Criterion(criterion("模版", FilterOp::Equals, &["详情"]));
+        let and = filter(FilterMode::And, vec![sku.clone(), other.clone()]);
+        let or = filter(FilterMode::Or, vec![sku, other]);
+        assert!(!and.matches(&fields));
+        assert!(or.matches(&fields));
+        let nested = filter(
+            FilterMode::And,
+            vec![
+                Condition::Criterion(criterion("SKU", FilterOp::BeginWith, &["A"])),
+                Condition::Group(or),
+            ],
+        );
+        assert!(nested.matches(&fields));
+    }
+
+    #[test]
+    fn airscript_criteria_only_for_flat_and_text_filters() {
+        let text = Condition::Criterion(criterion("SKU", FilterOp::Contains, &["A"]));
+        let empty = Condition::Criterion(criterion("模版", FilterOp::NotEmpty, &[]));
+        let pushed = filter(FilterMode::And, vec![text.clone(), empty.clone()]);
+        let criteria = pushed.airscript_criteria().unwrap();
+        assert_eq!(criteria.len(), 2);
+        assert_eq!(criteria[0].field, "SKU");
+
+        // OR 条件组、大小比较与嵌套条件组只在本地判断
+        let or = filter(FilterMode::Or, vec![text.clone(), empty]);
+        assert!(or.airscript_criteria().is_none());
+        let greater = Condition::Criterion(criterion("数量", FilterOp::Greater, &["1"]));
+        assert!(filter(FilterMode::And, vec![text.clone(), greater])
+            .airscript_criteria()
+            .is_none());
+        let nested = filter(FilterMode::And, vec![text, Condition::Group(or)]);
+        assert!(nested.airscript_criteria().is_none());
+    }
+
+    #[test]
+    fn serializes_ops_with_airscript_names() {
+        let value = serde_json::to_value(criterion("SKU", FilterOp::NotEquals, &["A"])).unwrap();
+        assert_eq!(
+            value,
+            json!({"field": "SKU", "op": "NotEqu", "values": ["A"]})
+        );
+    }
+}
@@ -1,10 +1,20 @@
-use chrono::Local;
-use log::info;
+use chrono::{DateTime, Local};
+use log::{error, info, warn};
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
-use rusqlite::{params, Connection};
+use rusqlite::Connection;
+use batch::ActiveBatches;
+use dispatch::BatchOptions;
+use filter::RecordFilter;
+use calendar::{CalendarInput, CalendarRecord};
+use events::SchedulerStatus;
+use run_lock::{OverlapPolicy, RunLock};
+use scheduler::{RowFilter, ScheduleInput, ScheduleRecord};
+use settings::{Settings, SettingsState};
+use tasks::TaskState;
+use trigger::{Trigger, TriggerSpec};
 use std::{
+    collections::HashMap,
     process::{Child, Command},
-    str::FromStr,
 };
 use tauri::{
     menu::{Menu, MenuItem},
@@ -12,69 +22,405 @@ use tauri::{
     Manager, WindowEvent,
 };
 use tauri_plugin_log::{Target, TargetKind};
-use cron::Schedule as CronSchedule;
 use tauri::async_runtime::JoinHandle as AsyncJoinHandle;
 use tokio::sync::Mutex;
 use uuid::Uuid;
+mod batch;
+mod calendar;
+mod db;
+mod dispatch;
+mod events;
+mod filter;
+mod models;
+mod run_lock;
+mod retry;
+mod scheduler;
+mod settings;
+mod tasks;
+mod trigger;
 mod wps_reader;
 
 struct ChildProcess(Mutex<Option<Child>>);
 
+/// 定时任务第一次触发前等待后台服务启动的最长时间
+const AUTOMATOR_STARTUP_WAIT: std::time::Duration = std::time::Duration::from_secs(60);
+
 struct Db(Mutex<Connection>);
 
-struct Schedule(Mutex<Option<AsyncJoinHandle<()>>>);
+/// 已启动的定时任务, key 为定时任务ID
+struct Schedules(Mutex<HashMap<String, AsyncJoinHandle<()>>>);
+
+/// 在定时循环中获取执行权, 批次在独立的任务中执行, 返回批次的任务(被跳过时为 None).
+/// 修改或停止定时任务只会中止定时循环(含排队等待中的触发), 不会中断已开始的批次
+async fn fire_schedule(
+    app: &tauri::AppHandle,
+    schedule_id: &str,
+    policy: OverlapPolicy,
+    options: &BatchOptions,
+) -> Option<AsyncJoinHandle<()>> {
+    let run_lock: tauri::State<RunLock> = app.state();
+    let permit = match run_lock.acquire(policy).await {
+        Ok(permit) => permit,
+        Err(reason) => {
+            warn!("定时任务 {} {}", schedule_id, reason);
+            events::tick_skipped(app, schedule_id, &reason);
+            let db_state: tauri::State<Db> = app.state();
+            let conn = db_state.0.lock().await;
+            let _ = scheduler::record_event(&conn, schedule_id, "skipped", &reason);
+            return None;
+        }
+    };
+    let app = app.clone();
+    let schedule_id = schedule_id.to_string();
+    let options = options.clone();
+    Some(tauri::async_runtime::spawn(async move {
+        let _permit = permit;
+        let batches: tauri::State<ActiveBatches> = app.state();
+        let batch = batches.begin(&schedule_id);
+        events::tick_started(&app, &schedule_id);
+        let result = dispatch::run_batch(&app, &options, &batch).await;
+        events::tick_finished(&app, &schedule_id, &result);
+    }))
+}
+
+fn spawn_schedule_loop(
+    app: tauri::AppHandle,
+    record: &ScheduleRecord,
+) -> Result<AsyncJoinHandle<()>, String> {
+    let trigger = Trigger::from_spec(&record.trigger)?;
+    let options = BatchOptions {
+        filter: record.filter(),
+        rerun: record.rerun,
+    };
+    let schedule_id = record.id.clone();
+    let overlap_policy = record.overlap_policy;
+    let misfire_policy = record.misfire_policy;
+    let calendar_ids = record.calendar_ids.clone();
+    let mut last_fire = record.last_fire().unwrap_or_else(Local::now);
+    Ok(tauri::async_runtime::spawn(async move {
+        // 只在下次触发时间变化时通知前端
+        let mut announced_next = None;
+        let mut automator_ready = false;
+        loop {
+            // 每次都以上次触发时间为基准计算, 应用重启或系统唤醒后可以发现错过的触发
+            let now = Local::now();
+            let due = scheduler::due_fires(&trigger, &last_fire, &now);
+            if due.is_empty() {
+                let Some(next_dt) = trigger.after(&now.max(last_fire)) else {
+                    info!("定时任务 {} 已没有后续触发", schedule_id);
+                    events::stopped(&app, &schedule_id, "已没有后续触发");
+                    break;
+                };
+                if announced_next != Some(next_dt) {
+                    announced_next = Some(next_dt);
+                    events::next_fire_at(&app, &schedule_id, &next_dt);
+                }
+                // 分段休眠, 每次醒来都重新对照系统时间
+                tokio::time::sleep(scheduler::sleep_step(&next_dt, &now)).await;
+                continue;
+            }
+
+            let (fires, misfire) = scheduler::plan_fires(&due, &now, misfire_policy);
+            last_fire = now;
+            let calendars = {
+                let db_state: tauri::State<Db> = app.state();
+                let conn = db_state.0.lock().await;
+                let _ = scheduler::save_last_fire(&conn, &schedule_id, &last_fire);
+                if let Some(message) = misfire.as_deref() {
+                    warn!("定时任务 {} {}", schedule_id, message);
+                    let _ = scheduler::record_event(&conn, &schedule_id, "misfire", message);
+                    if fires.is_empty() {
+                        events::tick_skipped(&app, &schedule_id, message);
+                    }
+                } else if let Some(fire) = due.last() {
+                    if let Some(secs) = scheduler::lateness(fire, &now) {
+                        let message = format!(
+                            "延迟 {} 秒触发(计划 {})",
+                            secs,
+                            fire.format("%Y-%m-%d %H:%M:%S")
+                        );
+                        warn!("定时任务 {} {}", schedule_id, message);
+                        let _ = scheduler::record_event(&conn, &schedule_id, "late", &message);
+                    }
+                }
+                // 每次触发时重新读取日历, 修改日历后无需重启定时任务
+                calendar::load_calendars(&conn, &calendar_ids).unwrap_or_else(|e| {
+                    error!("读取屏蔽日历失败: {}", e);
+                    Vec::new()
+                })
+            };
+            let mut fires = fires.into_iter().peekable();
+            if fires.peek().is_some() && !automator_ready {
+                // 程序刚启动时后台服务可能仍在启动, 立即补执行会全部拒绝连接
+                dispatch::wait_for_automator(&app, AUTOMATOR_STARTUP_WAIT).await;
+                automator_ready = true;
+            }
+            while let Some(fire) = fires.next() {
+                if let Some(reason) = calendar::blocked_reason(&calendars, &fire) {
+                    let message = format!(
+                        "{} 的触发被屏蔽: {}",
+                        fire.format("%Y-%m-%d %H:%M:%S"),
+                        reason
+                    );
+                    info!("定时任务 {} {}", schedule_id, message);
+                    events::tick_skipped(&app, &schedule_id, &message);
+                    let db_state: tauri::State<Db> = app.state();
+                    let conn = db_state.0.lock().await;
+                    let _ = scheduler::record_event(&conn, &schedule_id, "suppressed", &message);
+                    continue;
+                }
+                let handle = fire_schedule(&app, &schedule_id, overlap_policy, &options).await;
+                // 补跑多次时等上一批次结束再触发下一次, 否则会被自己的批次按重叠策略跳过
+                if let (Some(handle), Some(_)) = (handle, fires.peek()) {
+                    let _ = handle.await;
+                }
+            }
+        }
+    }))
+}
 
-fn normalize_cron(expr:&str) -> String{
-    if expr.split_whitespace().count() == 5{
-        format!("0 {}",expr)
-    }else{
-        expr.to_string()
+/// 停止该定时任务已有的循环, 若处于启用状态则按最新配置重新启动
+async fn arm_schedule(
+    app: &tauri::AppHandle,
+    schedules: &Schedules,
+    record: &ScheduleRecord,
+) -> Result<(), String> {
+    let mut guard = schedules.0.lock().await;
+    let previous = guard.remove(&record.id);
+    if let Some(h) = &previous {
+        h.abort();
+    }
+    if record.enabled {
+        let handle = spawn_schedule_loop(app.clone(), record)?;
+        guard.insert(record.id.clone(), handle);
+        events::armed(app, record);
+        info!(
+            "定时任务已启动: {} ({})",
+            record.name,
+            record.trigger.describe()
+        );
+    } else {
+        info!("定时任务已停止: {}", record.name);
+        if previous.is_some() {
+            events::stopped(app, &record.id, "定时任务已停止");
+        }
     }
+    Ok(())
 }
 
 #[tauri::command]
 async fn start_cron(
     app:tauri::AppHandle,
-    schedule:tauri::State<'_,Schedule>,
+    schedules:tauri::State<'_,Schedules>,
+    db: tauri::State<'_, Db>,
     cron:String,
+    rerun: Option<bool>,
 ) -> Result<(),String>{
     info!("设置定时任务: {}",cron);
-    if let Some(h) = schedule.0.lock().await.take(){
-        h.abort();
-    }
-    let expr = normalize_cron(&cron);
-    let cron_schedule = CronSchedule::from_str(&expr).map_err(|e|format!("解析cron表达式失败:{}",e))?;
-
-    let handle = tauri::async_runtime::spawn({
-        let app = app.clone();
-        async move{
-            let mut upcoming = cron_schedule.upcoming(Local);
-            loop {
-                let Some(next_dt) = upcoming.next() else {break};
-                let now = Local::now();
-                let dur = (next_dt -now)
-                .to_std()
-                .unwrap_or_else(|_| std::time::Duration::from_secs(0));
-                tokio::time::sleep(dur).await;
-
-                let db_state:tauri::State<Db> = app.state();
-                let _ = execute_task(db_state).await;
+    // “某天”类型传入的是ISO时间, 按单次执行处理
+    let trigger = match DateTime::parse_from_rfc3339(cron.trim()) {
+        Ok(at) => TriggerSpec::Once {
+            at: at.with_timezone(&Local).naive_local(),
+        },
+        Err(_) => TriggerSpec::Cron { expr: cron },
+    };
+    let record = {
+        let conn = db.0.lock().await;
+        let input = ScheduleInput {
+            name: scheduler::DEFAULT_SCHEDULE_NAME.to_string(),
+            cron: None,
+            trigger: Some(trigger),
+            enabled: Some(true),
+            sku_filter: None,
+            module_filter: None,
+            row_filter: None,
+            rerun,
+            overlap_policy: None,
+            misfire_policy: None,
+            calendar_ids: None,
+        };
+        let record = match scheduler::load_schedule(&conn, scheduler::DEFAULT_SCHEDULE_ID)
+            .map_err(|e| format!("数据库查询失败: {}", e))?
+        {
+            Some(mut record) => {
+                record.apply(input)?;
+                record
             }
-        }
-    });
-    *schedule.0.lock().await = Some(handle);
-    Ok(())
+            None => ScheduleRecord::new(scheduler::DEFAULT_SCHEDULE_ID.to_string(), input)?,
+        };
+        scheduler::save_schedule(&conn, &record)
+            .map_err(|e| format!("保存定时任务失败: {}", e))?;
+        record
+    };
+    arm_schedule(&app, &schedules, &record).await
 }
 
 #[tauri::command]
-async fn stop_cron(scheduler:tauri::State<'_,Schedule>) -> Result<(),String>{
+async fn stop_cron(
+    app: tauri::AppHandle,
+    schedules: tauri::State<'_, Schedules>,
+    db: tauri::State<'_, Db>,
+) -> Result<(), String> {
     info!("停止定时任务");
-    if let Some(h) = scheduler.0.lock().await.take(){
+    enable_schedule(
+        app,
+        schedules,
+        db,
+        scheduler::DEFAULT_SCHEDULE_ID.to_string(),
+        false,
+    )
+    .await
+}
+
+/// 使用与定时循环相同的解析逻辑, 返回校验错误或接下来的触发时间
+#[tauri::command]
+async fn preview_cron(cron: String, count: Option<usize>) -> Result<String, String> {
+    let count = count.unwrap_or(5).clamp(1, 50);
+    match trigger::validate_cron(&cron) {
+        Ok(cron_schedule) => {
+            let items: Vec<String> = cron_schedule
+                .upcoming(Local)
+                .take(count)
+                .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                .collect();
+            Ok(serde_json::json!({"status":"success","data":items}).to_string())
+        }
+        Err(e) => Ok(serde_json::json!({"status":"error","error":e}).to_string()),
+    }
+}
+
+/// 预览间隔、单次、时段等触发方式接下来的触发时间
+#[tauri::command]
+async fn preview_trigger(trigger: TriggerSpec, count: Option<usize>) -> Result<String, String> {
+    let count = count.unwrap_or(5).clamp(1, 50);
+    let trigger = match Trigger::from_spec(&trigger) {
+        Ok(trigger) => trigger,
+        Err(message) => {
+            return Ok(
+                serde_json::json!({"status":"error","error":{"message":message}}).to_string(),
+            )
+        }
+    };
+    let mut items = Vec::new();
+    let mut cursor = Local::now();
+    while let Some(next) = trigger.after(&cursor).filter(|_| items.len() < count) {
+        items.push(next.format("%Y-%m-%d %H:%M:%S").to_string());
+        cursor = next;
+    }
+    Ok(serde_json::json!({"status":"success","data":items}).to_string())
+}
+
+/// 各定时任务的运行状态, 与调度事件的载荷一致
+#[tauri::command]
+async fn get_scheduler_status(status: tauri::State<'_, SchedulerStatus>) -> Result<String, String> {
+    Ok(serde_json::json!({"status":"success","data":status.snapshot()}).to_string())
+}
+
+#[tauri::command]
+async fn list_schedules(
+    schedules: tauri::State<'_, Schedules>,
+    db: tauri::State<'_, Db>,
+) -> Result<String, String> {
+    let records = {
+        let conn = db.0.lock().await;
+        scheduler::list_schedules(&conn).map_err(|e| format!("数据库查询失败: {}", e))?
+    };
+    let running = schedules.0.lock().await;
+    let items: Vec<serde_json::Value> = records
+        .iter()
+        .map(|r| {
+            let mut item = serde_json::to_value(r).unwrap_or_default();
+            item["running"] = running
+                .get(&r.id)
+                .is_some_and(|h| !h.inner().is_finished())
+                .into();
+            item
+        })
+        .collect();
+    Ok(serde_json::json!({"status":"success","data":items}).to_string())
+}
+
+#[tauri::command]
+async fn create_schedule(
+    app: tauri::AppHandle,
+    schedules: tauri::State<'_, Schedules>,
+    db: tauri::State<'_, Db>,
+    schedule: ScheduleInput,
+) -> Result<String, String> {
+    let record = ScheduleRecord::new(Uuid::now_v7().to_string(), schedule)?;
+    {
+        let conn = db.0.lock().await;
+        scheduler::save_schedule(&conn, &record)
+            .map_err(|e| format!("保存定时任务失败: {}", e))?;
+    }
+    info!("新建定时任务: {} ({})", record.name, record.trigger.describe());
+    arm_schedule(&app, &schedules, &record).await?;
+    Ok(record.id)
+}
+
+#[tauri::command]
+async fn update_schedule(
+    app: tauri::AppHandle,
+    schedules: tauri::State<'_, Schedules>,
+    db: tauri::State<'_, Db>,
+    id: String,
+    schedule: ScheduleInput,
+) -> Result<(), String> {
+    let record = {
+        let conn = db.0.lock().await;
+        let mut record = scheduler::load_schedule(&conn, &id)
+            .map_err(|e| format!("数据库查询失败: {}", e))?
+            .ok_or_else(|| format!("定时任务不存在: {}", id))?;
+        record.apply(schedule)?;
+        scheduler::save_schedule(&conn, &record)
+            .map_err(|e| format!("保存定时任务失败: {}", e))?;
+        record
+    };
+    info!("修改定时任务: {} ({})", record.name, record.trigger.describe());
+    arm_schedule(&app, &schedules, &record).await
+}
+
+#[tauri::command]
+async fn delete_schedule(
+    app: tauri::AppHandle,
+    schedules: tauri::State<'_, Schedules>,
+    db: tauri::State<'_, Db>,
+    id: String,
+) -> Result<(), String> {
+    if let Some(h) = schedules.0.lock().await.remove(&id) {
         h.abort();
     }
+    events::removed(&app, &id);
+    let conn = db.0.lock().await;
+    scheduler::delete_schedule(&conn, &id).map_err(|e| format!("删除定时任务失败: {}", e))?;
+    info!("删除定时任务: {}", id);
     Ok(())
 }
 
+#[tauri::command]
+async fn enable_schedule(
+    app: tauri::AppHandle,
+    schedules: tauri::State<'_, Schedules>,
+    db: tauri::State<'_, Db>,
+    id: String,
+    enabled: bool,
+) -> Result<(), String> {
+    let record = {
+        let conn = db.0.lock().await;
+        let Some(mut record) = scheduler::load_schedule(&conn, &id)
+            .map_err(|e| format!("数据库查询失败: {}", e))?
+        else {
+            return Err(format!("定时任务不存在: {}", id));
+        };
+        record.enabled = enabled;
+        record.reset_last_fire();
+        scheduler::save_schedule(&conn, &record)
+            .map_err(|e| format!("保存定时任务失败: {}", e))?;
+        record
+    };
+    arm_schedule(&app, &schedules, &record).await
+}
+
 async fn stop_background_service(app: tauri::AppHandle) {
     let child_opt = {
         let state: tauri::State<ChildProcess> = app.state();
@@ -93,6 +439,7 @@ async fn stop_background_service(app: tauri::AppHandle) {
 async fn get_task_list(
     sku: Option<String>,
     module: Option<String>,
+    state: Option<String>,
     db: tauri::State<'_, Db>,
 ) -> Result<String, String> {
     let conn = db.0.lock().await;
@@ -103,7 +450,12 @@ async fn get_task_list(
           replace(substr(run_time,1,19),'T',' ') AS run_time,
           SKU,
           module,
-          status
+          status,
+          state,
+          replace(substr(started_at,1,19),'T',' ') AS started_at,
+          replace(substr(finished_at,1,19),'T',' ') AS finished_at,
+          duration_ms,
+          parent_task_id
         FROM tasks
         WHERE 1=1       
         "#,
@@ -117,6 +469,11 @@ async fn get_task_list(
         sql.push_str(" AND module like ?");
         binds.push(format!("%{}%", m));
     }
+    if let Some(st) = state.as_deref().filter(|st| !st.is_empty()) {
+        let st = TaskState::parse(st).ok_or_else(|| format!("未知的任务状态: {}", st))?;
+        sql.push_str(" AND state = ?");
+        binds.push(st.as_str().to_string());
+    }
     sql.push_str(" ORDER BY run_time DESC");
 
     let mut stmt = conn
@@ -138,6 +495,11 @@ async fn get_task_list(
             "SKU": row.get::<_,String>(2).unwrap_or_default(),
             "module": row.get::<_,String>(3).unwrap_or_default(),
             "status": row.get::<_,i32>(4).unwrap_or_default(),
+            "state": row.get::<_,String>(5).unwrap_or_default(),
+            "started_at": row.get::<_,Option<String>>(6).unwrap_or_default(),
+            "finished_at": row.get::<_,Option<String>>(7).unwrap_or_default(),
+            "duration_ms": row.get::<_,Option<i64>>(8).unwrap_or_default(),
+            "parent_task_id": row.get::<_,Option<String>>(9).unwrap_or_default(),
         }));
     }
     // 这里可以添加获取任务列表的逻辑
@@ -174,118 +536,337 @@ async fn get_task_logs(task_id: String, db: tauri::State<'_, Db>) -> Result<Stri
     Ok(serde_json::json!({"status":"success","data":items}).to_string())
 }
 
+/// 任务详情: 下发给 automator 的内容、最后一次请求的 HTTP 状态与原始响应及各阶段耗时
+#[tauri::command]
+async fn get_task_detail(task_id: String, db: tauri::State<'_, Db>) -> Result<String, String> {
+    let conn = db.0.lock().await;
+    let detail = tasks::load_detail(&conn, &task_id)
+        .map_err(|e| format!("数据库查询失败: {}", e))?
+        .ok_or_else(|| format!("任务不存在: {}", task_id))?;
+    Ok(serde_json::json!({"status":"success","data":detail}).to_string())
+}
+
 #[tauri::command]
-async fn get_data() -> Result<String, String> {
+async fn get_data(settings: tauri::State<'_, SettingsState>) -> Result<String, String> {
     println!("get_data");
     println!("获取数据被调用");
     // 这里可以添加获取数据的逻辑
-    match wps_reader::fetch_wps_data().await {
-        Ok(data) => {
-            println!("数据获取成功: {}", data);
-            return Ok(data);
+    let settings = settings.checked()?;
+    let page_size = settings.dispatch.page_size;
+    match wps_reader::fetch_wps_data(&settings.connection, &settings.wps_mapping, &[], page_size, None)
+        .await
+    {
+        Ok(page) => {
+            println!("数据获取成功: {} 条记录", page.data.len());
+            Ok(serde_json::json!({"status":"success","data":page}).to_string())
         }
         Err(e) => {
             eprintln!("数据获取失败: {}", e);
-            return Err("数据获取失败".to_string());
+            Err(format!("数据获取失败: {}", e))
+        }
+    }
+}
+
+/// 手动执行不排队, 已有批次在执行时记录跳过并返回原因
+async fn acquire_manual_run(
+    app: &tauri::AppHandle,
+    db: &Db,
+    run_lock: &RunLock,
+) -> Result<run_lock::RunPermit, String> {
+    match run_lock.acquire(OverlapPolicy::Skip).await {
+        Ok(permit) => Ok(permit),
+        Err(reason) => {
+            events::tick_skipped(app, scheduler::MANUAL_SCHEDULE_ID, &reason);
+            let conn = db.0.lock().await;
+            let _ = scheduler::record_event(&conn, scheduler::MANUAL_SCHEDULE_ID, "skipped", &reason);
+            Err(reason)
         }
     }
 }
 
+/// 手动执行时前端提交的筛选条件
+fn manual_filter(filter: Option<RecordFilter>) -> Result<RowFilter, String> {
+    if let Some(filter) = &filter {
+        filter.validate()?;
+    }
+    Ok(RowFilter {
+        criteria: filter,
+        ..RowFilter::default()
+    })
+}
+
 #[tauri::command]
-async fn execute_task(db: tauri::State<'_, Db>) -> Result<String, String> {
-    let data_str = wps_reader::fetch_wps_data()
-        .await
-        .map_err(|e| format!("数据获取失败: {}", e))?;
-    info!("获取到的数据: {}", data_str);
-    let v: serde_json::Value =
-        serde_json::from_str(&data_str).map_err(|e| format!("JSON解析失败: {}", e))?;
-    let Some(items) = v.get("data").and_then(|d| d.as_array()) else {
-        return Err("数据获取失败: 响应体中没有 'data' 字段".to_string());
+async fn execute_task(
+    app: tauri::AppHandle,
+    db: tauri::State<'_, Db>,
+    run_lock: tauri::State<'_, RunLock>,
+    batches: tauri::State<'_, ActiveBatches>,
+    rerun: Option<bool>,
+    dry_run: Option<bool>,
+    filter: Option<RecordFilter>,
+) -> Result<String, String> {
+    let options = BatchOptions {
+        filter: manual_filter(filter)?,
+        rerun: rerun.unwrap_or(false),
     };
-    let client = reqwest::Client::new();
-    let mut inserted = 0;
+    // 试运行不下发任务, 不需要等待正在执行的批次
+    if dry_run.unwrap_or(false) {
+        let plan = dispatch::plan_batch(&app, &options).await?;
+        return Ok(serde_json::json!({"status":"success","data":plan}).to_string());
+    }
+    let _permit = acquire_manual_run(&app, &db, &run_lock).await?;
+    let batch = batches.begin(scheduler::MANUAL_SCHEDULE_ID);
+    events::tick_started(&app, scheduler::MANUAL_SCHEDULE_ID);
+    let result = dispatch::run_batch(&app, &options, &batch).await;
+    events::tick_finished(&app, scheduler::MANUAL_SCHEDULE_ID, &result);
+    Ok(serde_json::json!({"status":"success","data":result?}).to_string())
+}
 
-    for item in items {
-        let Some(fields) = item.get("fields") else {
-            continue;
-        };
-        let row_id = item.get("id").and_then(|v| v.as_str()).unwrap_or("");
-        let sku = fields.get("SKU").and_then(|v| v.as_str()).unwrap_or("");
-        let module = fields
-            .get("调用PS模版")
-            .and_then(|v| v.as_str())
-            .unwrap_or("");
-        if sku.is_empty() || module.is_empty() {
-            continue;
-        }
-        let task_id = Uuid::now_v7().to_string();
-        let run_time = chrono::Local::now().to_rfc3339();
-        let create_time = fields
-            .get("创建时间")
-            .and_then(|v| v.as_str())
-            .unwrap_or("");
-        {
-            let conn = db.0.lock().await;
-            conn.execute(
-                "INSERT INTO tasks (task_id, run_time, SKU, module, create_time, status)
-                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-                params![task_id, run_time, sku, module, create_time, 0],
-            )
-            .map_err(|e| format!("数据库插入失败: {}", e))?;
-        }
-        let resp = client
-            .post("http://127.0.0.1:5000/automator")
-            .json(fields)
-            .send()
-            .await
-            .map_err(|e| format!("请求后台服务失败: {}", e))?;
-        let json: serde_json::Value = resp.json().await.unwrap_or(serde_json::json!({}));
-        if json.get("status").and_then(|v| v.as_str()) == Some("error") {
-            let message = json
-                .get("message")
-                .and_then(|v| v.as_str())
-                .unwrap_or("未知错误");
+/// 以保存的下发内容重新执行一个失败、超时或已取消的任务, 新任务指向原任务
+#[tauri::command]
+async fn rerun_task(
+    app: tauri::AppHandle,
+    db: tauri::State<'_, Db>,
+    run_lock: tauri::State<'_, RunLock>,
+    batches: tauri::State<'_, ActiveBatches>,
+    task_id: String,
+) -> Result<String, String> {
+    run_manual_rerun(&app, &db, &run_lock, &batches, vec![task_id]).await
+}
+
+/// 重新执行符合任务列表查询条件的全部失败与超时任务, 已经重新执行过的任务不再重复执行
+#[tauri::command]
+async fn rerun_failed_tasks(
+    app: tauri::AppHandle,
+    db: tauri::State<'_, Db>,
+    run_lock: tauri::State<'_, RunLock>,
+    batches: tauri::State<'_, ActiveBatches>,
+    sku: Option<String>,
+    module: Option<String>,
+) -> Result<String, String> {
+    let task_ids = {
+        let conn = db.0.lock().await;
+        tasks::failed_task_ids(
+            &conn,
+            sku.as_deref().filter(|s| !s.is_empty()),
+            module.as_deref().filter(|m| !m.is_empty()),
+        )
+        .map_err(|e| format!("数据库查询失败: {}", e))?
+    };
+    run_manual_rerun(&app, &db, &run_lock, &batches, task_ids).await
+}
+
+async fn run_manual_rerun(
+    app: &tauri::AppHandle,
+    db: &Db,
+    run_lock: &RunLock,
+    batches: &ActiveBatches,
+    task_ids: Vec<String>,
+) -> Result<String, String> {
+    let _permit = acquire_manual_run(app, db, run_lock).await?;
+    let batch = batches.begin(scheduler::MANUAL_SCHEDULE_ID);
+    events::tick_started(app, scheduler::MANUAL_SCHEDULE_ID);
+    let result = dispatch::rerun_tasks(app, &task_ids, &batch).await;
+    events::tick_finished(app, scheduler::MANUAL_SCHEDULE_ID, &result);
+    Ok(serde_json::json!({"status":"success","data":result?}).to_string())
+}
+
+/// 试运行: 返回将要下发的行与每行不能下发的原因, 不创建任务、不请求 automator、不回写 WPS.
+/// 指定 schedule_id 时使用该定时任务的筛选条件, 否则使用 filter
+#[tauri::command]
+async fn plan_batch(
+    app: tauri::AppHandle,
+    db: tauri::State<'_, Db>,
+    schedule_id: Option<String>,
+    rerun: Option<bool>,
+    filter: Option<RecordFilter>,
+) -> Result<String, String> {
+    let filter = match schedule_id {
+        Some(id) => {
             let conn = db.0.lock().await;
-            conn.execute(
-                "INSERT INTO task_logs (task_id, log_time, message) VALUES (?1, ?2, ?3)",
-                params![
-                    task_id,
-                    chrono::Local::now().to_rfc3339(),
-                    format!("任务执行失败: {}", message)
-                ],
-            )
-            .map_err(|e| format!("日志插入失败: {}", e))?;
-            wps_reader::update_wps_date(&row_id, "否")
-                .await
-                .map_err(|e| format!("更新任务状态失败: {}", e))?;
-            continue;
-        }
-        if let Some(logs) = json.get("logs").and_then(|v| v.as_array()) {
-            for log in logs {
-                let message = log[1].as_str().unwrap_or("无日志信息");
-                let log_time = log[0].as_str().unwrap_or("");
-                let conn = db.0.lock().await;
-                conn.execute(
-                    "INSERT INTO task_logs (task_id, log_time, message) VALUES (?1, ?2, ?3)",
-                    params![task_id, log_time, message],
-                )
-                .map_err(|e| format!("日志插入失败: {}", e))?;
-            }
+            scheduler::load_schedule(&conn, &id)
+                .map_err(|e| format!("数据库查询失败: {}", e))?
+                .ok_or_else(|| format!("定时任务不存在: {}", id))?
+                .filter()
         }
-        inserted += 1;
-        wps_reader::update_wps_date(&row_id, "是")
-            .await
-            .map_err(|e| format!("更新任务状态失败: {}", e))?;
-        let conn = db.0.lock().await;
-        conn.execute(
-            "UPDATE tasks SET status = 1 WHERE task_id = ?1",
-            params![task_id],
+        None => manual_filter(filter)?,
+    };
+    let options = BatchOptions {
+        filter,
+        rerun: rerun.unwrap_or(false),
+    };
+    let plan = dispatch::plan_batch(&app, &options).await?;
+    Ok(serde_json::json!({"status":"success","data":plan}).to_string())
+}
+
+#[tauri::command]
+async fn get_settings(settings: tauri::State<'_, SettingsState>) -> Result<String, String> {
+    Ok(serde_json::json!({
+        "status":"success",
+        "data":settings.get(),
+        "problems":settings.problems()
+    })
+    .to_string())
+}
+
+/// 校验并保存设置, 下一个批次开始时生效
+#[tauri::command]
+async fn update_settings(
+    state: tauri::State<'_, SettingsState>,
+    settings: Settings,
+) -> Result<(), String> {
+    state.update(settings)?;
+    info!("设置已更新");
+    Ok(())
+}
+
+/// 检查设置是否可以正常执行, 未传入时检查当前设置. 不保存, 供设置页面在保存前提示
+#[tauri::command]
+async fn validate_settings(
+    state: tauri::State<'_, SettingsState>,
+    settings: Option<Settings>,
+) -> Result<String, String> {
+    let problems = match settings {
+        Some(settings) => settings.problems(),
+        None => state.problems(),
+    };
+    Ok(serde_json::json!({
+        "status":"success",
+        "data":{"valid":problems.is_empty(),"problems":problems}
+    })
+    .to_string())
+}
+
+/// 取消正在执行的批次, 可以按批次ID或定时任务ID(手动执行为 manual)指定, 都未指定时取消全部.
+/// 剩余的行不再下发, 执行中的任务通知 automator 取消, 已取消的任务不回写 WPS, 下次执行时仍会被选中
+#[tauri::command]
+async fn cancel_current_run(
+    batches: tauri::State<'_, ActiveBatches>,
+    batch_id: Option<String>,
+    schedule_id: Option<String>,
+) -> Result<String, String> {
+    let cancelled = batches.cancel(batch_id.as_deref(), schedule_id.as_deref());
+    if cancelled.is_empty() {
+        return Err("当前没有正在执行的批次".to_string());
+    }
+    for batch in &cancelled {
+        info!("请求取消批次: {} ({})", batch.batch_id, batch.schedule_id);
+    }
+    Ok(serde_json::json!({"status":"success","data":cancelled}).to_string())
+}
+
+/// 取消单个排队中或执行中的任务, 其余任务不受影响
+#[tauri::command]
+async fn cancel_task(
+    batches: tauri::State<'_, ActiveBatches>,
+    task_id: String,
+) -> Result<(), String> {
+    if !batches.cancel_task(&task_id) {
+        return Err(format!("任务不在执行中: {}", task_id));
+    }
+    info!("请求取消任务: {}", task_id);
+    Ok(())
+}
+
+#[tauri::command]
+async fn list_calendars(db: tauri::State<'_, Db>) -> Result<String, String> {
+    let conn = db.0.lock().await;
+    let items = calendar::list_calendars(&conn).map_err(|e| format!("数据库查询失败: {}", e))?;
+    Ok(serde_json::json!({"status":"success","data":items}).to_string())
+}
+
+#[tauri::command]
+async fn create_calendar(
+    db: tauri::State<'_, Db>,
+    calendar: CalendarInput,
+) -> Result<String, String> {
+    let record = CalendarRecord::new(Uuid::now_v7().to_string(), calendar)?;
+    let conn = db.0.lock().await;
+    calendar::save_calendar(&conn, &record).map_err(|e| format!("保存日历失败: {}", e))?;
+    info!("新建屏蔽日历: {}", record.name);
+    Ok(record.id)
+}
+
+#[tauri::command]
+async fn update_calendar(
+    db: tauri::State<'_, Db>,
+    id: String,
+    calendar: CalendarInput,
+) -> Result<(), String> {
+    let conn = db.0.lock().await;
+    let mut record = calendar::load_calendar(&conn, &id)
+        .map_err(|e| format!("数据库查询失败: {}", e))?
+        .ok_or_else(|| format!("日历不存在: {}", id))?;
+    record.apply(calendar)?;
+    calendar::save_calendar(&conn, &record).map_err(|e| format!("保存日历失败: {}", e))?;
+    info!("修改屏蔽日历: {}", record.name);
+    Ok(())
+}
+
+#[tauri::command]
+async fn delete_calendar(db: tauri::State<'_, Db>, id: String) -> Result<(), String> {
+    let conn = db.0.lock().await;
+    calendar::delete_calendar(&conn, &id).map_err(|e| format!("删除日历失败: {}", e))?;
+    info!("删除屏蔽日历: {}", id);
+    Ok(())
+}
+
+/// 将 ICS 文件中的事件追加到日历, 返回导入的条数
+#[tauri::command]
+async fn import_calendar_ics(
+    db: tauri::State<'_, Db>,
+    id: String,
+    path: String,
+) -> Result<usize, String> {
+    let content =
+        std::fs::read_to_string(&path).map_err(|e| format!("读取ICS文件失败: {}", e))?;
+    let rules = calendar::parse_ics(&content)?;
+    let conn = db.0.lock().await;
+    let mut record = calendar::load_calendar(&conn, &id)
+        .map_err(|e| format!("数据库查询失败: {}", e))?
+        .ok_or_else(|| format!("日历不存在: {}", id))?;
+    let count = rules.len();
+    record.rules.extend(rules);
+    record.updated_at = Local::now().to_rfc3339();
+    calendar::save_calendar(&conn, &record).map_err(|e| format!("保存日历失败: {}", e))?;
+    info!("日历 {} 导入 {} 条屏蔽时间", record.name, count);
+    Ok(count)
+}
+
+#[tauri::command]
+async fn get_schedule_logs(schedule_id: String, db: tauri::State<'_, Db>) -> Result<String, String> {
+    let conn = db.0.lock().await;
+    let mut stmt = conn
+        .prepare(
+            r#"
+         select
+            log_time,
+            kind,
+            message
+         from schedule_logs
+         where
+            schedule_id = ?1
+        order by log_time desc
+        limit 200
+        "#,
         )
-        .map_err(|e| format!("更新任务状态失败: {}", e))?;
+        .map_err(|e| format!("数据库查询失败: {}", e))?;
+    let mut rows = stmt
+        .query(rusqlite::params![schedule_id])
+        .map_err(|e| format!("数据库查询失败: {}", e))?;
+
+    let mut items = Vec::new();
+    while let Some(row) = rows.next().map_err(|e| format!("读取行失败!:{}", e))? {
+        items.push(serde_json::json!({
+            "log_time": row.get::<_,String>(0).unwrap_or_default(),
+            "kind": row.get::<_,String>(1).unwrap_or_default(),
+            "message": row.get::<_,String>(2).unwrap_or_default(),
+        }));
     }
-    Ok(format!("任务执行完成, 共插入 {} 条任务", inserted))
+    Ok(serde_json::json!({"status":"success","data":items}).to_string())
 }
 
+
 #[tauri::command]
 async fn open_timing_window(app: tauri::AppHandle) {
     let lable = "timing";
@@ -365,7 +946,10 @@ pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_clipboard_manager::init())
         .manage(ChildProcess(Mutex::new(None)))
-        .manage(Schedule(Mutex::new(None)))
+        .manage(Schedules(Mutex::new(HashMap::new())))
+        .manage(RunLock::default())
+        .manage(SchedulerStatus::default())
+        .manage(ActiveBatches::default())
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_log::Builder::new().build())
         .plugin(tauri_plugin_store::Builder::new().build())
@@ -393,15 +977,53 @@ pub fn run() {
             get_data,
             open_logs_window,
             execute_task,
+            plan_batch,
+            rerun_task,
+            rerun_failed_tasks,
+            cancel_current_run,
+            cancel_task,
+            get_settings,
+            validate_settings,
+            update_settings,
             get_task_list,
             get_task_logs,
+            get_task_detail,
             start_cron,
-            stop_cron
+            stop_cron,
+            get_scheduler_status,
+            preview_cron,
+            preview_trigger,
+            list_schedules,
+            create_schedule,
+            update_schedule,
+            delete_schedule,
+            enable_schedule,
+            get_schedule_logs,
+            list_calendars,
+            create_calendar,
+            update_calendar,
+            delete_calendar,
+            import_calendar_ics
         ])
         .setup(|app| {
             // 初始化Sqlite数据库
             let data_dir = app.path().app_data_dir().map_err(|e| anyhow::anyhow!(e))?;
             std::fs::create_dir_all(&data_dir)?;
+            // 设置文件损坏时不影响程序启动, 也不覆盖原文件
+            let settings_state = SettingsState::open(data_dir.join("settings.json"));
+            let settings = settings_state.get();
+            if let Err(e) = settings.connection.check_wps() {
+                warn!("{}", e);
+            }
+            if let Err(e) = settings.validate() {
+                error!("设置有误, 修改前不会执行批次: {}", e);
+            }
+            let mapping = &settings.wps_mapping;
+            info!(
+                "WPS表格: {}, 读取 {} = {} 且 {} 为空的行",
+                mapping.sheet, mapping.eligible_column, mapping.eligible_value, mapping.result_column
+            );
+            app.manage(settings_state);
             let db_path = data_dir.join("app_data.db");
             println!("数据库路径: {:?}", db_path);
             let conn = Connection::open(&db_path)?;
@@ -429,6 +1051,14 @@ pub fn run() {
             "#,
                 (),
             )?;
+            tasks::migrate(&conn)?;
+            let interrupted = tasks::recover_interrupted(&conn)?;
+            if interrupted > 0 {
+                warn!("上次退出时有 {} 个任务未完成, 已标记为失败", interrupted);
+            }
+            scheduler::create_table(&conn)?;
+            calendar::create_table(&conn)?;
+            let saved_schedules = scheduler::list_schedules(&conn)?;
             app.manage(Db(Mutex::new(conn)));
             // 启动后台服务
             let exe_path = app
@@ -456,22 +1086,31 @@ pub fn run() {
             } else {
                 eprintln!("未找到后台服务可执行文件: {:?}", exe_path);
             }
+            // 恢复上次保存的定时任务, 在启动后台服务之后, 补执行前会等待后台服务可以连接
+            let schedules: tauri::State<Schedules> = app.state();
+            tauri::async_runtime::block_on(async {
+                for record in saved_schedules.iter().filter(|r| r.enabled) {
+                    if let Err(e) = arm_schedule(app.handle(), &schedules, record).await {
+                        error!("恢复定时任务失败: {} {}", record.name, e);
+                    }
+                }
+            });
 
             let show_i = MenuItem::with_id(app, "show", "显示", true, None::<&str>)?;
+            let cancel_i = MenuItem::with_id(app, "cancel_run", "取消当前批次", true, None::<&str>)?;
             let quit_i = MenuItem::with_id(app, "quit", "退出", true, None::<&str>)?;
-            let menu = Menu::with_items(app, &[&show_i, &quit_i])?;
+            let menu = Menu::with_items(app, &[&show_i, &cancel_i, &quit_i])?;
             let _tray = TrayIconBuilder::new()
                 .icon(app.default_window_icon().unwrap().clone())
                 .menu(&menu)
                 .show_menu_on_left_click(false)
-                .on_tray_icon_event(|tray, event| match event {
-                    TrayIconEvent::Click {
-                        id: _,
-                        position: _,
-                        rect: _,
+                .on_tray_icon_event(|tray, event| {
+                    if let TrayIconEvent::Click {
                         button: MouseButton::Left,
                         button_state: MouseButtonState::Up,
-                    } => {
+                        ..
+                    } = event
+                    {
                         let win = tray
                             .app_handle()
                             .get_webview_window("main")
@@ -485,7 +1124,6 @@ pub fn run() {
                         };
                         win.set_focus().unwrap();
                     }
-                    _ => {}
                 })
                 .on_menu_event(|app, event| match event.id.as_ref() {
                     "show" => {
@@ -499,6 +1137,12 @@ pub fn run() {
                         };
                         win.set_focus().unwrap();
                     }
+                    "cancel_run" => {
+                        let batches: tauri::State<ActiveBatches> = app.state();
+                        for batch in batches.cancel(None, None) {
+                            info!("请求取消批次: {} ({})", batch.batch_id, batch.schedule_id);
+                        }
+                    }
                     "quit" => {
                         let app_cloned = app.clone();
                         tauri::async_runtime::spawn(async move {
@@ -512,14 +1156,10 @@ pub fn run() {
             Ok(())
         })
         .on_window_event(|window, event| {
-            let app_handle = window.app_handle().clone();
             if let WindowEvent::CloseRequested { api, .. } = event {
-                match window.label() {
-                    "main" => {
-                        api.prevent_close(); // 阻止窗口关闭
-                        let _ = window.hide(); // 隐藏窗口
-                    }
-                    _ => {}
+                if window.label() == "main" {
+                    api.prevent_close(); // 阻止窗口关闭
+                    let _ = window.hide(); // 隐藏窗口
                 }
             };
             if let WindowEvent::Destroyed = event {
@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+
+/// WPS Airscript 读取接口的一页响应, 每条记录单独解析, 一条记录格式不对不影响其余记录
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WpsResponse {
+    pub data: Vec<serde_json::Value>,
+    /// 下一页的游标, 没有下一页时为空
+    #[serde(default)]
+    pub offset: Option<String>,
+}
+
+/// WPS 表格中的一行. 列名由设置中的表格映射决定, 下发给 automator 的是原始的 fields
+#[derive(Debug, Deserialize)]
+pub struct WpsRecord {
+    /// 记录ID, 回写 WPS 时使用
+    pub id: String,
+    pub fields: serde_json::Map<String, serde_json::Value>,
+}
+
+impl WpsRecord {
+    pub fn parse(item: &serde_json::Value) -> Result<WpsRecord, String> {
+        WpsRecord::deserialize(item).map_err(|e| format!("记录解析失败: {}", e))
+    }
+
+    /// 读取必填的文本列, 缺少该列或不是文本时返回错误
+    pub fn text(&self, column: &str) -> Result<&str, String> {
+        match self.fields.get(column) {
+            Some(serde_json::Value::String(value)) => Ok(value),
+            Some(value) => Err(format!("列 {} 不是文本: {}", column, value)),
+            None => Err(format!("记录中没有列 {}", column)),
+        }
+    }
+
+    /// 读取可以为空的文本列, 缺少该列时为空字符串
+    pub fn optional_text(&self, column: &str) -> Result<&str, String> {
+        match self.fields.get(column) {
+            None | Some(serde_json::Value::Null) => Ok(""),
+            Some(_) => self.text(column),
+        }
+    }
+}
+
+/// automator 的 /automator 接口的响应
+#[derive(Debug, Deserialize)]
+pub struct AutomatorResponse {
+    /// 为 "error" 时表示渲染失败
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub message: Option<String>,
+    #[serde(default)]
+    pub logs: Vec<AutomatorLog>,
+}
+
+impl AutomatorResponse {
+    /// 渲染失败时返回错误信息
+    pub fn error(&self) -> Option<&str> {
+        (self.status.as_deref() == Some("error"))
+            .then(|| self.message.as_deref().unwrap_or("未知错误"))
+    }
+}
+
+/// automator 的一条日志, 格式为 [时间, 内容]. 格式不对的日志保留原文,
+/// 不影响整个响应的解析
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum AutomatorLog {
+    Entry(String, String),
+    Malformed(serde_json::Value),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record() -> WpsRecord {
+        WpsRecord::parse(&json!({
+            "id": "rec1",
+            "fields": {"SKU": "A1", "数量": 3, "备注": null}
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_record() {
+        let record = record();
+        assert_eq!(record.id, "rec1");
+        assert_eq!(record.fields.len(), 3);
+        let err = WpsRecord::parse(&json!({"fields": {}})).unwrap_err();
+        assert!(err.starts_with("记录解析失败"), "{}", err);
+        assert!(WpsRecord::parse(&json!({"id": "rec1", "fields": []})).is_err());
+    }
+
+    #[test]
+    fn text_requires_string_column() {
+        let record = record();
+        assert_eq!(record.text("SKU"), Ok("A1"));
+        assert_eq!(record.text("数量"), Err("列 数量 不是文本: 3".to_string()));
+        assert_eq!(
+            record.text("备注"),
+            Err("列 备注 不是文本: null".to_string())
+        );
+        assert_eq!(record.text("模版"), Err("记录中没有列 模版".to_string()));
+    }
+
+    #[test]
+    fn optional_text_allows_missing_and_null() {
+        let record = record();
+        assert_eq!(record.optional_text("SKU"), Ok("A1"));
+        assert_eq!(record.optional_text("备注"), Ok(""));
+        assert_eq!(record.optional_text("模版"), Ok(""));
+        assert!(record.optional_text("数量").is_err());
+    }
+
+    #[test]
+    fn response_offset_defaults_to_none() {
+        let response: WpsResponse = serde_json::from_value(json!({"data": []})).unwrap();
+        assert!(response.offset.is_none());
+    }
+
+    #[test]
+    fn automator_error() {
+        let parse = |value| serde_json::from_value::<AutomatorResponse>(value).unwrap();
+        assert_eq!(
+            parse(json!({"status": "error", "message": "图层不存在"})).error(),
+            Some("图层不存在")
+        );
+        assert_eq!(parse(json!({"status": "error"})).error(), Some("未知错误"));
+        assert_eq!(
+            parse(json!({"status": "success", "message": "ok"})).error(),
+            None
+        );
+        assert_eq!(parse(json!({})).error(), None);
+    }
+
+    #[test]
+    fn malformed_logs_are_kept() {
+        let response: AutomatorResponse = serde_json::from_value(json!({
+            "status": "success",
+            "logs": [["10:00", "打开模版"], null, [1, 2], "完成"]
+        }))
+        .unwrap();
+        assert_eq!(response.logs.len(), 4);
+        assert!(matches!(
+            &response.logs[0],
+            AutomatorLog::Entry(time, message) if time == "10:00" && message == "打开模版"
+        ));
+        assert!(matches!(
+            &response.logs[1],
+            AutomatorLog::Malformed(serde_json::Value::Null)
+        ));
+        assert!(
+            matches!(&response.logs[2], AutomatorLog::Malformed(value) if value == &json!([1, 2]))
+        );
+        assert!(matches!(&response.logs[3], AutomatorLog::Malformed(value) if value == "完成"));
+    }
+}
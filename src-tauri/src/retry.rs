@@ -0,0 +1,138 @@
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// 重试策略, 第 n 次重试前等待 initial_delay_ms * multiplier^(n-1), 不超过 max_delay_ms,
+/// 并在此基础上随机浮动 ±jitter, 避免多个任务同时重试
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// 最多尝试次数(含第一次), 为1时不重试
+    pub max_attempts: u32,
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+    pub multiplier: f64,
+    /// 0 ~ 1 之间的浮动比例
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_delay_ms: 1000,
+            max_delay_ms: 30_000,
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn validate(&self, name: &str) -> Result<(), String> {
+        if self.max_attempts == 0 {
+            return Err(format!("{}的最多尝试次数必须大于0", name));
+        }
+        if self.multiplier < 1.0 {
+            return Err(format!("{}的退避倍数不能小于1", name));
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err(format!("{}的随机浮动比例应在0 ~ 1之间", name));
+        }
+        if self.max_delay_ms < self.initial_delay_ms {
+            return Err(format!("{}的最大等待时间不能小于初始等待时间", name));
+        }
+        Ok(())
+    }
+
+    /// 第 attempt 次尝试失败后, 下一次尝试前的等待时间
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let base = self.initial_delay_ms as f64 * self.multiplier.powi(attempt as i32 - 1);
+        let base = base.min(self.max_delay_ms as f64);
+        let factor = 1.0 + self.jitter * (2.0 * random_unit() - 1.0);
+        Duration::from_millis((base * factor).max(0.0) as u64)
+    }
+}
+
+/// [0, 1) 之间的随机数, 只用于重试等待的随机浮动
+fn random_unit() -> f64 {
+    let value = RandomState::new().build_hasher().finish();
+    (value >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// 失败分类: 临时性失败(服务启动中拒绝连接、超时、5xx)可以重试, 其余不重试
+#[derive(Debug)]
+pub enum Failure {
+    Transient(String),
+    Permanent(String),
+    /// 超过调用方设置的时间限制, 重试大概率同样超时(例如 Photoshop 卡在对话框), 不重试
+    TimedOut(String),
+}
+
+impl Failure {
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Failure::Transient(_))
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Transient(message)
+            | Failure::Permanent(message)
+            | Failure::TimedOut(message) => f.write_str(message),
+        }
+    }
+}
+
+impl From<Failure> for String {
+    fn from(failure: Failure) -> Self {
+        failure.to_string()
+    }
+}
+
+/// 按 reqwest 错误类型分类, context 为错误信息前缀
+pub fn classify(context: &str, e: &reqwest::Error) -> Failure {
+    let message = format!("{}: {}", context, e);
+    let server_error = e.status().is_some_and(|s| s.is_server_error());
+    if e.is_connect() || e.is_timeout() || server_error {
+        Failure::Transient(message)
+    } else {
+        Failure::Permanent(message)
+    }
+}
+
+/// 按策略执行 op, 每次尝试后调用 on_attempt(第几次, 失败原因(成功时为None), 是否还会重试) 记录日志
+pub async fn run<T, F, Fut, L, LFut>(
+    policy: &RetryPolicy,
+    mut op: F,
+    mut on_attempt: L,
+) -> Result<T, Failure>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Failure>>,
+    L: FnMut(u32, Option<&Failure>, bool) -> LFut,
+    LFut: Future<Output = ()>,
+{
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Ok(value) => {
+                on_attempt(attempt, None, false).await;
+                return Ok(value);
+            }
+            Err(failure) => {
+                let retry = failure.is_transient() && attempt < policy.max_attempts;
+                on_attempt(attempt, Some(&failure), retry).await;
+                if !retry {
+                    return Err(failure);
+                }
+                tokio::time::sleep(policy.delay(attempt)).await;
+                attempt += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// 上一批次尚未结束时, 新触发的批次如何处理
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverlapPolicy {
+    /// 跳过本次触发
+    #[default]
+    Skip,
+    /// 最多排队一个批次, 等上一批次结束后执行
+    Queue,
+    /// 不做限制, 与正在执行的批次并行
+    Parallel,
+}
+
+impl OverlapPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OverlapPolicy::Skip => "skip",
+            OverlapPolicy::Queue => "queue",
+            OverlapPolicy::Parallel => "parallel",
+        }
+    }
+
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "queue" => OverlapPolicy::Queue,
+            "parallel" => OverlapPolicy::Parallel,
+            _ => OverlapPolicy::Skip,
+        }
+    }
+}
+
+#[derive(Default)]
+struct RunSlot {
+    active: usize,
+    queued: bool,
+}
+
+#[derive(Default)]
+struct Inner {
+    slot: Mutex<RunSlot>,
+    released: Notify,
+}
+
+/// 批次执行锁, 定时任务与手动执行共用, 防止同一批 WPS 记录被重复下发
+#[derive(Clone, Default)]
+pub struct RunLock(Arc<Inner>);
+
+/// 持有期间视为批次正在执行, 释放时唤醒排队的批次
+pub struct RunPermit(Arc<Inner>);
+
+impl Drop for RunPermit {
+    fn drop(&mut self) {
+        let mut slot = self.0.slot.lock().unwrap();
+        slot.active -= 1;
+        if slot.active == 0 {
+            self.0.released.notify_one();
+        }
+    }
+}
+
+/// 排队中的批次被取消(例如定时任务被重新配置)时释放排队位置
+struct QueueTicket<'a> {
+    inner: &'a Inner,
+    waiting: bool,
+}
+
+impl Drop for QueueTicket<'_> {
+    fn drop(&mut self) {
+        if self.waiting {
+            self.inner.slot.lock().unwrap().queued = false;
+        }
+    }
+}
+
+impl RunLock {
+    /// 按策略获取执行权, 返回 Err 时为跳过原因
+    pub async fn acquire(&self, policy: OverlapPolicy) -> Result<RunPermit, String> {
+        {
+            let mut slot = self.0.slot.lock().unwrap();
+            match policy {
+                OverlapPolicy::Parallel => {
+                    slot.active += 1;
+                    return Ok(RunPermit(self.0.clone()));
+                }
+                _ if slot.active == 0 => {
+                    slot.active += 1;
+                    return Ok(RunPermit(self.0.clone()));
+                }
+                OverlapPolicy::Skip => {
+                    return Err("上一批次仍在执行, 跳过本次触发".to_string());
+                }
+                OverlapPolicy::Queue if slot.queued => {
+                    return Err("已有一个批次在排队, 跳过本次触发".to_string());
+                }
+                OverlapPolicy::Queue => slot.queued = true,
+            }
+        }
+        let mut ticket = QueueTicket {
+            inner: &self.0,
+            waiting: true,
+        };
+        loop {
+            self.0.released.notified().await;
+            let mut slot = self.0.slot.lock().unwrap();
+            if slot.active == 0 {
+                slot.active += 1;
+                slot.queued = false;
+                ticket.waiting = false;
+                return Ok(RunPermit(self.0.clone()));
+            }
+        }
+    }
+}
@@ -0,0 +1,592 @@
+use chrono::{DateTime, Local};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::time::Duration;
+
+use crate::db::ensure_column;
+use crate::filter::RecordFilter;
+use crate::run_lock::OverlapPolicy;
+use crate::trigger::{Trigger, TriggerSpec};
+
+/// 主窗口“开始定时/停止定时”所使用的定时任务ID
+pub const DEFAULT_SCHEDULE_ID: &str = "default";
+pub const DEFAULT_SCHEDULE_NAME: &str = "默认定时";
+/// 手动点击“执行”时记录到 schedule_logs 的ID
+pub const MANUAL_SCHEDULE_ID: &str = "manual";
+/// 触发时间晚于计划时间超过该秒数时视为错过(应用未运行或系统休眠)
+const MISFIRE_GRACE_SECS: i64 = 60;
+/// 单次补偿最多统计的错过次数, 避免长时间关闭后逐次补执行过多批次
+const MAX_CATCH_UP_FIRES: usize = 100;
+/// 等待下次触发时单次休眠的上限, 醒来后重新对照系统时间,
+/// 以应对系统休眠、NTP校时和夏令时切换
+const MAX_SLEEP_STEP: Duration = Duration::from_secs(30);
+/// 实际触发时间晚于计划时间超过该秒数时记录延迟
+const LATE_FIRE_SECS: i64 = 2;
+
+/// 错过触发时间(应用关闭、系统休眠)后的补偿策略
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MisfirePolicy {
+    /// 立即补执行一次
+    #[default]
+    FireOnce,
+    /// 每个错过的时间点都补执行
+    FireAll,
+    /// 忽略错过的触发, 等待下一次
+    Ignore,
+}
+
+impl MisfirePolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MisfirePolicy::FireOnce => "fire_once",
+            MisfirePolicy::FireAll => "fire_all",
+            MisfirePolicy::Ignore => "ignore",
+        }
+    }
+
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "fire_all" => MisfirePolicy::FireAll,
+            "ignore" => MisfirePolicy::Ignore,
+            _ => MisfirePolicy::FireOnce,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduleRecord {
+    pub id: String,
+    pub name: String,
+    pub trigger: TriggerSpec,
+    pub enabled: bool,
+    pub sku_filter: Option<String>,
+    pub module_filter: Option<String>,
+    pub row_filter: Option<RecordFilter>,
+    /// 为 true 时已成功的行(在 WPS 中清空了执行结果)也重新下发
+    pub rerun: bool,
+    pub overlap_policy: OverlapPolicy,
+    pub misfire_policy: MisfirePolicy,
+    pub calendar_ids: Vec<String>,
+    pub last_fire_time: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// 前端创建/修改定时任务时提交的内容, trigger 与 cron 二选一.
+/// 未提交的字段保持原值; 筛选条件提交 null(sku、模版也可以是空字符串)时清除
+#[derive(Debug, Deserialize)]
+pub struct ScheduleInput {
+    pub name: String,
+    pub cron: Option<String>,
+    pub trigger: Option<TriggerSpec>,
+    pub enabled: Option<bool>,
+    #[serde(default, deserialize_with = "present")]
+    pub sku_filter: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub module_filter: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub row_filter: Option<Option<RecordFilter>>,
+    pub rerun: Option<bool>,
+    pub overlap_policy: Option<OverlapPolicy>,
+    pub misfire_policy: Option<MisfirePolicy>,
+    pub calendar_ids: Option<Vec<String>>,
+}
+
+/// 定时任务或手动执行对 WPS 记录的筛选. sku 与 module 按包含关系匹配(与日志查询一致),
+/// criteria 为任意列上的筛选条件
+#[derive(Debug, Clone, Default)]
+pub struct RowFilter {
+    pub sku: Option<String>,
+    pub module: Option<String>,
+    pub criteria: Option<RecordFilter>,
+}
+
+/// 区分未提交的字段(None)与提交了 null 的字段(Some(None))
+fn present<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+impl RowFilter {
+    pub fn matches(&self, sku: &str, module: &str, fields: &serde_json::Value) -> bool {
+        self.sku.as_deref().is_none_or(|s| sku.contains(s))
+            && self.module.as_deref().is_none_or(|m| module.contains(m))
+            && self.criteria.as_ref().is_none_or(|c| c.matches(fields))
+    }
+}
+
+impl ScheduleRecord {
+    pub fn new(id: String, input: ScheduleInput) -> Result<Self, String> {
+        let now = chrono::Local::now().to_rfc3339();
+        let mut record = ScheduleRecord {
+            id,
+            name: String::new(),
+            trigger: TriggerSpec::Cron {
+                expr: String::new(),
+            },
+            enabled: true,
+            sku_filter: None,
+            module_filter: None,
+            row_filter: None,
+            rerun: false,
+            overlap_policy: OverlapPolicy::default(),
+            misfire_policy: MisfirePolicy::default(),
+            calendar_ids: Vec::new(),
+            last_fire_time: None,
+            created_at: now.clone(),
+            updated_at: now,
+        };
+        record.apply(input)?;
+        Ok(record)
+    }
+
+    pub fn apply(&mut self, input: ScheduleInput) -> Result<(), String> {
+        let name = input.name.trim();
+        if name.is_empty() {
+            return Err("定时任务名称不能为空".to_string());
+        }
+        let trigger = match (input.trigger, input.cron) {
+            (Some(trigger), _) => trigger,
+            (None, Some(expr)) => TriggerSpec::Cron {
+                expr: expr.trim().to_string(),
+            },
+            (None, None) => return Err("请设置定时任务的触发方式".to_string()),
+        };
+        Trigger::from_spec(&trigger)?;
+        if let Some(Some(filter)) = &input.row_filter {
+            filter.validate()?;
+        }
+        if let TriggerSpec::Once { at } = &trigger {
+            if *at <= Local::now().naive_local() {
+                return Err(format!("执行时间已过: {}", at));
+            }
+        }
+        self.name = name.to_string();
+        self.trigger = trigger;
+        if let Some(enabled) = input.enabled {
+            self.enabled = enabled;
+        }
+        if let Some(sku) = input.sku_filter {
+            self.sku_filter = non_empty(sku);
+        }
+        if let Some(module) = input.module_filter {
+            self.module_filter = non_empty(module);
+        }
+        if let Some(row_filter) = input.row_filter {
+            self.row_filter = row_filter;
+        }
+        if let Some(rerun) = input.rerun {
+            self.rerun = rerun;
+        }
+        if let Some(policy) = input.overlap_policy {
+            self.overlap_policy = policy;
+        }
+        if let Some(policy) = input.misfire_policy {
+            self.misfire_policy = policy;
+        }
+        if let Some(calendar_ids) = input.calendar_ids {
+            self.calendar_ids = calendar_ids;
+        }
+        self.reset_last_fire();
+        Ok(())
+    }
+
+    /// 配置变更或重新启用后从当前时间开始计算, 不补偿之前的触发
+    pub fn reset_last_fire(&mut self) {
+        let now = chrono::Local::now().to_rfc3339();
+        self.last_fire_time = Some(now.clone());
+        self.updated_at = now;
+    }
+
+    pub fn last_fire(&self) -> Option<DateTime<Local>> {
+        self.last_fire_time
+            .as_deref()
+            .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+            .map(|t| t.with_timezone(&Local))
+    }
+
+    pub fn filter(&self) -> RowFilter {
+        RowFilter {
+            sku: self.sku_filter.clone(),
+            module: self.module_filter.clone(),
+            criteria: self.row_filter.clone(),
+        }
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        // 旧版本只保存了cron表达式
+        let trigger = row
+            .get::<_, Option<String>>(12)?
+            .and_then(|t| serde_json::from_str(&t).ok());
+        Ok(ScheduleRecord {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            trigger: match trigger {
+                Some(trigger) => trigger,
+                None => TriggerSpec::Cron { expr: row.get(2)? },
+            },
+            enabled: row.get::<_, i32>(3)? != 0,
+            sku_filter: row.get(4)?,
+            module_filter: row.get(5)?,
+            overlap_policy: OverlapPolicy::parse(&row.get::<_, String>(6)?),
+            misfire_policy: MisfirePolicy::parse(&row.get::<_, String>(7)?),
+            calendar_ids: serde_json::from_str(&row.get::<_, String>(8)?).unwrap_or_default(),
+            last_fire_time: row.get(9)?,
+            created_at: row.get(10)?,
+            updated_at: row.get(11)?,
+            row_filter: row
+                .get::<_, Option<String>>(13)?
+                .and_then(|f| serde_json::from_str(&f).ok()),
+            rerun: row.get::<_, i32>(14)? != 0,
+        })
+    }
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+pub fn create_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        r#"
+        create table if not exists schedules(
+         id text primary key,
+         name text not null default '',
+         cron text not null,
+         trigger text,
+         enabled integer not null,
+         sku_filter text,
+         module_filter text,
+         overlap_policy text not null default 'skip',
+         misfire_policy text not null default 'fire_once',
+         calendar_ids text not null default '[]',
+         last_fire_time text,
+         created_at text not null default '',
+         updated_at text not null
+        )
+    "#,
+        (),
+    )?;
+    conn.execute(
+        r#"
+        create table if not exists schedule_logs(
+         id integer primary key autoincrement,
+         schedule_id text not null,
+         log_time text not null,
+         kind text not null,
+         message text not null
+        )
+    "#,
+        (),
+    )?;
+    ensure_column(conn, "schedules", "name", "text not null default ''")?;
+    ensure_column(conn, "schedules", "trigger", "text")?;
+    ensure_column(conn, "schedules", "sku_filter", "text")?;
+    ensure_column(conn, "schedules", "module_filter", "text")?;
+    ensure_column(conn, "schedules", "row_filter", "text")?;
+    ensure_column(conn, "schedules", "rerun", "integer not null default 0")?;
+    ensure_column(
+        conn,
+        "schedules",
+        "overlap_policy",
+        "text not null default 'skip'",
+    )?;
+    ensure_column(
+        conn,
+        "schedules",
+        "misfire_policy",
+        "text not null default 'fire_once'",
+    )?;
+    ensure_column(
+        conn,
+        "schedules",
+        "calendar_ids",
+        "text not null default '[]'",
+    )?;
+    ensure_column(conn, "schedules", "last_fire_time", "text")?;
+    ensure_column(conn, "schedules", "created_at", "text not null default ''")?;
+    Ok(())
+}
+
+const SELECT_COLUMNS: &str = "SELECT id, name, cron, enabled, sku_filter, module_filter, overlap_policy, misfire_policy, calendar_ids, last_fire_time, created_at, updated_at, trigger, row_filter, rerun FROM schedules";
+
+pub fn load_schedule(conn: &Connection, id: &str) -> rusqlite::Result<Option<ScheduleRecord>> {
+    conn.query_row(
+        &format!("{} WHERE id = ?1", SELECT_COLUMNS),
+        params![id],
+        ScheduleRecord::from_row,
+    )
+    .optional()
+}
+
+pub fn list_schedules(conn: &Connection) -> rusqlite::Result<Vec<ScheduleRecord>> {
+    let mut stmt = conn.prepare(&format!("{} ORDER BY created_at", SELECT_COLUMNS))?;
+    let rows = stmt.query_map((), ScheduleRecord::from_row)?;
+    rows.collect()
+}
+
+pub fn save_schedule(conn: &Connection, record: &ScheduleRecord) -> rusqlite::Result<()> {
+    let cron = match &record.trigger {
+        TriggerSpec::Cron { expr } => expr.as_str(),
+        _ => "",
+    };
+    conn.execute(
+        r#"
+        INSERT INTO schedules (id, name, cron, enabled, sku_filter, module_filter, overlap_policy, misfire_policy, calendar_ids, last_fire_time, created_at, updated_at, trigger, row_filter, rerun)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)
+        ON CONFLICT(id) DO UPDATE SET
+            name = excluded.name,
+            cron = excluded.cron,
+            trigger = excluded.trigger,
+            enabled = excluded.enabled,
+            sku_filter = excluded.sku_filter,
+            module_filter = excluded.module_filter,
+            row_filter = excluded.row_filter,
+            rerun = excluded.rerun,
+            overlap_policy = excluded.overlap_policy,
+            misfire_policy = excluded.misfire_policy,
+            calendar_ids = excluded.calendar_ids,
+            last_fire_time = excluded.last_fire_time,
+            updated_at = excluded.updated_at
+        "#,
+        params![
+            record.id,
+            record.name,
+            cron,
+            record.enabled as i32,
+            record.sku_filter,
+            record.module_filter,
+            record.overlap_policy.as_str(),
+            record.misfire_policy.as_str(),
+            serde_json::to_string(&record.calendar_ids).unwrap_or_else(|_| "[]".to_string()),
+            record.last_fire_time,
+            record.created_at,
+            record.updated_at,
+            serde_json::to_string(&record.trigger).ok(),
+            record
+                .row_filter
+                .as_ref()
+                .and_then(|f| serde_json::to_string(f).ok()),
+            record.rerun as i32
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn delete_schedule(conn: &Connection, id: &str) -> rusqlite::Result<usize> {
+    conn.execute("DELETE FROM schedules WHERE id = ?1", params![id])
+}
+
+/// 记录定时任务的触发情况(跳过、屏蔽等), 供日志窗口查看
+pub fn record_event(
+    conn: &Connection,
+    schedule_id: &str,
+    kind: &str,
+    message: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO schedule_logs (schedule_id, log_time, kind, message) VALUES (?1, ?2, ?3, ?4)",
+        params![
+            schedule_id,
+            chrono::Local::now().to_rfc3339(),
+            kind,
+            message
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn save_last_fire(
+    conn: &Connection,
+    id: &str,
+    last_fire: &DateTime<Local>,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE schedules SET last_fire_time = ?2 WHERE id = ?1",
+        params![id, last_fire.to_rfc3339()],
+    )?;
+    Ok(())
+}
+
+/// 上次触发之后、当前时间之前所有应触发的时间点
+pub fn due_fires(
+    trigger: &Trigger,
+    last_fire: &DateTime<Local>,
+    now: &DateTime<Local>,
+) -> Vec<DateTime<Local>> {
+    let mut due = Vec::new();
+    let mut cursor = *last_fire;
+    while let Some(next) = trigger.after(&cursor).filter(|t| t <= now) {
+        due.push(next);
+        if due.len() >= MAX_CATCH_UP_FIRES {
+            break;
+        }
+        cursor = next;
+    }
+    due
+}
+
+/// 根据到期的触发时间和补偿策略计算需要执行的触发时间点, 有错过的触发时一并返回说明
+pub fn plan_fires(
+    due: &[DateTime<Local>],
+    now: &DateTime<Local>,
+    policy: MisfirePolicy,
+) -> (Vec<DateTime<Local>>, Option<String>) {
+    let (Some(first), Some(last)) = (due.first(), due.last()) else {
+        return (Vec::new(), None);
+    };
+    if due.len() == 1 && (*now - *first).num_seconds() <= MISFIRE_GRACE_SECS {
+        return (vec![*first], None);
+    }
+    let count = if due.len() >= MAX_CATCH_UP_FIRES {
+        format!("至少 {}", due.len())
+    } else {
+        due.len().to_string()
+    };
+    let missed = format!(
+        "错过 {} 次触发(最早 {})",
+        count,
+        first.format("%Y-%m-%d %H:%M:%S")
+    );
+    match policy {
+        MisfirePolicy::FireOnce => (vec![*last], Some(format!("{}, 立即补执行一次", missed))),
+        MisfirePolicy::FireAll => (due.to_vec(), Some(format!("{}, 逐次补执行", missed))),
+        MisfirePolicy::Ignore => (Vec::new(), Some(format!("{}, 按策略忽略", missed))),
+    }
+}
+
+/// 距下次触发的休眠时长, 不超过 MAX_SLEEP_STEP
+pub fn sleep_step(next_fire: &DateTime<Local>, now: &DateTime<Local>) -> Duration {
+    (*next_fire - *now)
+        .to_std()
+        .unwrap_or(Duration::ZERO)
+        .min(MAX_SLEEP_STEP)
+}
+
+/// 触发延迟超过阈值时返回延迟秒数
+pub fn lateness(fire: &DateTime<Local>, now: &DateTime<Local>) -> Option<i64> {
+    let secs = (*now - *fire).num_seconds();
+    (secs > LATE_FIRE_SECS).then_some(secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(day: u32, hour: u32, minute: u32, second: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2026, 6, day, hour, minute, second)
+            .unwrap()
+    }
+
+    fn every_minute() -> Trigger {
+        Trigger::Interval {
+            start: at(10, 9, 0, 0),
+            every: chrono::Duration::minutes(1),
+        }
+    }
+
+    #[test]
+    fn due_fires_lists_each_fire_since_last_fire() {
+        let due = due_fires(&every_minute(), &at(10, 9, 0, 0), &at(10, 9, 3, 30));
+        assert_eq!(due, vec![at(10, 9, 1, 0), at(10, 9, 2, 0), at(10, 9, 3, 0)]);
+    }
+
+    #[test]
+    fn due_fires_is_empty_before_next_fire() {
+        let due = due_fires(&every_minute(), &at(10, 9, 1, 0), &at(10, 9, 1, 59));
+        assert!(due.is_empty());
+    }
+
+    #[test]
+    fn due_fires_stops_at_catch_up_limit() {
+        let due = due_fires(&every_minute(), &at(10, 9, 0, 0), &at(12, 9, 0, 0));
+        assert_eq!(due.len(), MAX_CATCH_UP_FIRES);
+        assert_eq!(due[0], at(10, 9, 1, 0));
+    }
+
+    #[test]
+    fn plan_fires_runs_fire_within_grace_window() {
+        let due = [at(10, 2, 0, 0)];
+        for policy in [
+            MisfirePolicy::FireOnce,
+            MisfirePolicy::FireAll,
+            MisfirePolicy::Ignore,
+        ] {
+            let (fires, misfire) = plan_fires(&due, &at(10, 2, 1, 0), policy);
+            assert_eq!(fires, vec![at(10, 2, 0, 0)]);
+            assert_eq!(misfire, None);
+        }
+    }
+
+    #[test]
+    fn plan_fires_treats_late_single_fire_as_misfire() {
+        let due = [at(10, 2, 0, 0)];
+        let (fires, misfire) = plan_fires(&due, &at(10, 2, 1, 1), MisfirePolicy::Ignore);
+        assert!(fires.is_empty());
+        assert!(misfire.unwrap().contains("错过 1 次触发"));
+    }
+
+    #[test]
+    fn plan_fires_fire_once_runs_latest_missed_fire() {
+        let due = [at(10, 2, 0, 0), at(11, 2, 0, 0), at(12, 2, 0, 0)];
+        let (fires, misfire) = plan_fires(&due, &at(12, 8, 0, 0), MisfirePolicy::FireOnce);
+        assert_eq!(fires, vec![at(12, 2, 0, 0)]);
+        let misfire = misfire.unwrap();
+        assert!(misfire.contains("错过 3 次触发(最早 2026-06-10 02:00:00)"));
+        assert!(misfire.contains("立即补执行一次"));
+    }
+
+    #[test]
+    fn plan_fires_fire_all_runs_every_missed_fire() {
+        let due = [at(10, 2, 0, 0), at(11, 2, 0, 0), at(12, 2, 0, 0)];
+        let (fires, misfire) = plan_fires(&due, &at(12, 8, 0, 0), MisfirePolicy::FireAll);
+        assert_eq!(fires, due.to_vec());
+        assert!(misfire.unwrap().contains("逐次补执行"));
+    }
+
+    #[test]
+    fn plan_fires_ignore_skips_missed_fires() {
+        let due = [at(10, 2, 0, 0), at(11, 2, 0, 0)];
+        let (fires, misfire) = plan_fires(&due, &at(12, 8, 0, 0), MisfirePolicy::Ignore);
+        assert!(fires.is_empty());
+        assert!(misfire.unwrap().contains("按策略忽略"));
+    }
+
+    #[test]
+    fn plan_fires_reports_catch_up_limit() {
+        let due = due_fires(&every_minute(), &at(10, 9, 0, 0), &at(12, 9, 0, 0));
+        let (fires, misfire) = plan_fires(&due, &at(12, 9, 0, 0), MisfirePolicy::FireOnce);
+        assert_eq!(fires, vec![*due.last().unwrap()]);
+        assert!(misfire.unwrap().contains("错过 至少 100 次触发"));
+    }
+
+    #[test]
+    fn plan_fires_without_due_fires_does_nothing() {
+        let (fires, misfire) = plan_fires(&[], &at(10, 9, 0, 0), MisfirePolicy::FireAll);
+        assert!(fires.is_empty());
+        assert_eq!(misfire, None);
+    }
+
+    #[test]
+    fn sleep_step_is_bounded() {
+        let now = at(10, 9, 0, 0);
+        assert_eq!(sleep_step(&at(10, 9, 0, 10), &now), Duration::from_secs(10));
+        assert_eq!(sleep_step(&at(10, 9, 5, 0), &now), MAX_SLEEP_STEP);
+        assert_eq!(sleep_step(&at(10, 8, 59, 0), &now), Duration::ZERO);
+    }
+
+    #[test]
+    fn lateness_ignores_small_delays() {
+        let fire = at(10, 2, 0, 0);
+        assert_eq!(lateness(&fire, &at(10, 2, 0, 2)), None);
+        assert_eq!(lateness(&fire, &at(10, 2, 0, 3)), Some(3));
+    }
+}
@@ -0,0 +1,340 @@
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::Duration;
+
+use crate::filter::RecordFilter;
+use crate::retry::RetryPolicy;
+
+/// 程序设置, 保存在应用数据目录的 settings.json 中, 缺少的字段使用默认值
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub connection: ConnectionSettings,
+    pub dispatch: DispatchSettings,
+    pub retry: RetrySettings,
+    pub wps_mapping: WpsMapping,
+}
+
+/// WPS Airscript 与 automator 的连接设置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConnectionSettings {
+    /// WPS Airscript 脚本的调用地址
+    pub wps_url: String,
+    pub airscript_token: String,
+    /// 读取与回写 WPS 的超时时间(秒)
+    pub wps_timeout_secs: u64,
+    /// automator 服务地址, 下发与取消任务分别请求其下的 /automator 与 /cancel
+    pub automator_url: String,
+}
+
+impl Default for ConnectionSettings {
+    fn default() -> Self {
+        ConnectionSettings {
+            wps_url: String::new(),
+            airscript_token: String::new(),
+            wps_timeout_secs: 60,
+            automator_url: "http://127.0.0.1:5000".to_string(),
+        }
+    }
+}
+
+impl ConnectionSettings {
+    /// 检查已填写的内容, WPS 地址与 Token 可以暂不填写(保存后再补充)
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.wps_url.is_empty() {
+            check_url("WPS地址", &self.wps_url)?;
+        }
+        if reqwest::header::HeaderValue::from_str(&self.airscript_token).is_err() {
+            return Err("Airscript Token 含有无效字符".to_string());
+        }
+        if self.wps_timeout_secs == 0 {
+            return Err("WPS请求超时时间必须大于0".to_string());
+        }
+        check_url("automator地址", &self.automator_url)
+    }
+
+    /// 读取或回写 WPS 前检查是否已填写连接信息
+    pub fn check_wps(&self) -> Result<(), String> {
+        if self.wps_url.trim().is_empty() {
+            return Err("未设置WPS地址, 请在设置中填写".to_string());
+        }
+        if self.airscript_token.trim().is_empty() {
+            return Err("未设置Airscript Token, 请在设置中填写".to_string());
+        }
+        Ok(())
+    }
+
+    /// automator 下的接口地址, path 如 "automator"
+    pub fn automator_endpoint(&self, path: &str) -> String {
+        format!("{}/{}", self.automator_url.trim_end_matches('/'), path)
+    }
+}
+
+fn check_url(name: &str, url: &str) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("{}无效: {}", name, e))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(format!("{}必须以 http:// 或 https:// 开头", name));
+    }
+    Ok(())
+}
+
+/// 向 automator 下发任务的并发设置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DispatchSettings {
+    /// 同时执行的任务数上限, 默认为1即逐条执行
+    pub max_concurrency: usize,
+    /// 按PS模版单独限制并发数, 未配置的模版只受总并发数限制
+    pub module_limits: HashMap<String, usize>,
+    /// 单次请求 automator 等待结果的最长时间(秒), 超时的任务不重试
+    pub request_timeout_secs: u64,
+    /// 按PS模版单独设置超时时间(秒), 未配置的模版使用 request_timeout_secs
+    pub module_timeouts: HashMap<String, u64>,
+    /// 所有批次都要满足的筛选条件, 与定时任务或手动执行的筛选条件同时生效
+    pub row_filter: Option<RecordFilter>,
+    /// 每次从 WPS 读取的行数, 读取一页即开始下发, 不等待全部读完
+    pub page_size: usize,
+    /// 每个批次最多下发的行数, 其余行留到下一次执行. 为空时不限制
+    pub max_rows_per_run: Option<usize>,
+}
+
+impl Default for DispatchSettings {
+    fn default() -> Self {
+        DispatchSettings {
+            max_concurrency: 1,
+            module_limits: HashMap::new(),
+            request_timeout_secs: 600,
+            module_timeouts: HashMap::new(),
+            row_filter: None,
+            page_size: 100,
+            max_rows_per_run: None,
+        }
+    }
+}
+
+impl DispatchSettings {
+    pub fn timeout_for(&self, module: &str) -> Duration {
+        let secs = self
+            .module_timeouts
+            .get(module)
+            .copied()
+            .unwrap_or(self.request_timeout_secs);
+        Duration::from_secs(secs)
+    }
+}
+
+/// 临时性失败的重试策略
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetrySettings {
+    /// 请求 automator
+    pub automator: RetryPolicy,
+    /// 读取与回写 WPS
+    pub wps: RetryPolicy,
+}
+
+/// WPS 表格的表名与列名, 默认值为原有的表格结构
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WpsMapping {
+    pub sheet: String,
+    pub sku_column: String,
+    pub module_column: String,
+    pub create_time_column: String,
+    /// 只读取该列等于 eligible_value 的行
+    pub eligible_column: String,
+    pub eligible_value: String,
+    /// 执行结果回写到该列, 该列为空的行才会被读取
+    pub result_column: String,
+    pub result_success: String,
+    pub result_failure: String,
+}
+
+impl Default for WpsMapping {
+    fn default() -> Self {
+        WpsMapping {
+            sheet: "数据表".to_string(),
+            sku_column: "SKU".to_string(),
+            module_column: "调用PS模版".to_string(),
+            create_time_column: "创建时间".to_string(),
+            eligible_column: "设计师确认是否已完整填写可运行".to_string(),
+            eligible_value: "是".to_string(),
+            result_column: "是否运行完成".to_string(),
+            result_success: "是".to_string(),
+            result_failure: "否".to_string(),
+        }
+    }
+}
+
+impl WpsMapping {
+    pub fn validate(&self) -> Result<(), String> {
+        let required = [
+            (&self.sheet, "表名"),
+            (&self.sku_column, "SKU列"),
+            (&self.module_column, "PS模版列"),
+            (&self.eligible_column, "可运行标记列"),
+            (&self.eligible_value, "可运行标记值"),
+            (&self.result_column, "执行结果列"),
+            (&self.result_success, "执行成功的回写值"),
+            (&self.result_failure, "执行失败的回写值"),
+        ];
+        if let Some((_, name)) = required.iter().find(|(value, _)| value.trim().is_empty()) {
+            return Err(format!("WPS表格映射的{}不能为空", name));
+        }
+        // 相同时无法从表格中区分执行结果
+        if self.result_success == self.result_failure {
+            return Err("WPS表格映射中执行成功与失败的回写值不能相同".to_string());
+        }
+        if self.result_column == self.eligible_column {
+            return Err("WPS表格映射中执行结果列不能与可运行标记列相同".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Settings {
+    pub fn validate(&self) -> Result<(), String> {
+        self.connection.validate()?;
+        if self.dispatch.max_concurrency == 0 {
+            return Err("最大并发数必须大于0".to_string());
+        }
+        if let Some((module, _)) = self.dispatch.module_limits.iter().find(|(_, n)| **n == 0) {
+            return Err(format!("模版 {} 的并发数必须大于0", module));
+        }
+        if self.dispatch.request_timeout_secs == 0 {
+            return Err("请求超时时间必须大于0".to_string());
+        }
+        if let Some((module, _)) = self.dispatch.module_timeouts.iter().find(|(_, n)| **n == 0) {
+            return Err(format!("模版 {} 的超时时间必须大于0", module));
+        }
+        if self.dispatch.page_size == 0 {
+            return Err("每页读取的行数必须大于0".to_string());
+        }
+        if self.dispatch.max_rows_per_run == Some(0) {
+            return Err("每批次最多下发的行数必须大于0".to_string());
+        }
+        if let Some(filter) = &self.dispatch.row_filter {
+            filter.validate()?;
+        }
+        self.retry.automator.validate("automator重试策略")?;
+        self.retry.wps.validate("WPS重试策略")?;
+        self.wps_mapping.validate()?;
+        Ok(())
+    }
+
+    /// 设置中的全部问题, 包括尚未填写的连接信息, 为空时可以正常执行
+    pub fn problems(&self) -> Vec<String> {
+        self.validate()
+            .err()
+            .into_iter()
+            .chain(self.connection.check_wps().err())
+            .collect()
+    }
+
+    /// 旧版本从 .env 读取 WPS 连接信息, 只在还没有设置文件时导入一次, 返回是否导入
+    fn import_env(&mut self) -> bool {
+        dotenv::dotenv().ok();
+        let (Ok(url), Ok(token)) = (env::var("wps_url"), env::var("Airscript_Token")) else {
+            return false;
+        };
+        self.connection.wps_url = url.trim().to_string();
+        self.connection.airscript_token = token.trim().to_string();
+        true
+    }
+}
+
+/// 读取设置文件. 内容有误时仍返回读取到的设置, 由 SettingsState::checked 在执行前拦截,
+/// 不用默认的表格映射代替, 以免读取或回写错误的表格
+pub fn load(path: &Path) -> Result<Settings, String> {
+    if !path.exists() {
+        return Ok(Settings::default());
+    }
+    let content = std::fs::read_to_string(path).map_err(|e| format!("读取设置失败: {}", e))?;
+    let settings: Settings =
+        serde_json::from_str(&content).map_err(|e| format!("解析设置失败: {}", e))?;
+    Ok(settings)
+}
+
+pub fn save(path: &Path, settings: &Settings) -> Result<(), String> {
+    let content =
+        serde_json::to_string_pretty(settings).map_err(|e| format!("序列化设置失败: {}", e))?;
+    std::fs::write(path, content).map_err(|e| format!("保存设置失败: {}", e))
+}
+
+/// 启动时加载一次, 修改后立即生效(已开始的批次仍使用开始时的设置)
+pub struct SettingsState {
+    path: PathBuf,
+    current: RwLock<Settings>,
+    /// 设置文件无法读取时的原因, 保存新的设置前不执行批次
+    load_error: RwLock<Option<String>>,
+}
+
+impl SettingsState {
+    /// 读取设置文件. 文件不存在时使用默认设置并导入 .env 中的连接信息;
+    /// 无法读取时使用默认设置但不覆盖原文件, 错误由 problems 返回
+    pub fn open(path: PathBuf) -> Self {
+        let mut load_error = None;
+        let settings = if path.exists() {
+            load(&path).unwrap_or_else(|e| {
+                error!("{}, 保存新的设置前不会执行批次", e);
+                load_error = Some(e);
+                Settings::default()
+            })
+        } else {
+            let mut settings = Settings::default();
+            if settings.import_env() {
+                match settings.validate().and_then(|_| save(&path, &settings)) {
+                    Ok(()) => info!("已从 .env 导入 WPS 连接设置"),
+                    Err(e) => {
+                        error!("导入 .env 中的 WPS 连接设置失败: {}", e);
+                        settings = Settings::default();
+                    }
+                }
+            }
+            settings
+        };
+        SettingsState {
+            path,
+            current: RwLock::new(settings),
+            load_error: RwLock::new(load_error),
+        }
+    }
+
+    pub fn get(&self) -> Settings {
+        self.current.read().unwrap().clone()
+    }
+
+    /// 当前设置的全部问题, 包括设置文件无法读取的原因
+    pub fn problems(&self) -> Vec<String> {
+        let load_error = self.load_error.read().unwrap().clone();
+        load_error
+            .into_iter()
+            .chain(self.get().problems())
+            .collect()
+    }
+
+    /// 执行批次前读取设置, 设置有误时返回错误
+    pub fn checked(&self) -> Result<Settings, String> {
+        if let Some(e) = self.load_error.read().unwrap().as_ref() {
+            return Err(format!("{}, 请在设置中重新保存后再执行", e));
+        }
+        let settings = self.get();
+        settings
+            .validate()
+            .map_err(|e| format!("设置有误, 请修改后再执行: {}", e))?;
+        Ok(settings)
+    }
+
+    pub fn update(&self, settings: Settings) -> Result<(), String> {
+        settings.validate()?;
+        save(&self.path, &settings)?;
+        *self.current.write().unwrap() = settings;
+        *self.load_error.write().unwrap() = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,402 @@
+use chrono::{DateTime, Local};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::db;
+
+/// 任务状态, 以文本保存在 tasks.state 中
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    /// 已创建, 等待并发名额
+    Queued,
+    /// 已下发给 automator
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+    TimedOut,
+    Skipped,
+}
+
+impl TaskState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskState::Queued => "queued",
+            TaskState::Running => "running",
+            TaskState::Succeeded => "succeeded",
+            TaskState::Failed => "failed",
+            TaskState::Cancelled => "cancelled",
+            TaskState::TimedOut => "timed_out",
+            TaskState::Skipped => "skipped",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Some(match value {
+            "queued" => TaskState::Queued,
+            "running" => TaskState::Running,
+            "succeeded" => TaskState::Succeeded,
+            "failed" => TaskState::Failed,
+            "cancelled" => TaskState::Cancelled,
+            "timed_out" => TaskState::TimedOut,
+            "skipped" => TaskState::Skipped,
+            _ => return None,
+        })
+    }
+
+    /// 允许的状态变化, 结束状态不能再改变(重新执行会创建新的任务)
+    pub fn can_transition(&self, to: TaskState) -> bool {
+        use TaskState::*;
+        matches!(
+            (self, to),
+            (Queued, Running | Cancelled | Skipped)
+                | (Running, Succeeded | Failed | Cancelled | TimedOut)
+        )
+    }
+
+    /// 兼容旧版本的 tasks.status: 0 执行中, 1 成功, 2 失败
+    fn legacy_status(&self) -> i32 {
+        match self {
+            TaskState::Queued | TaskState::Running => 0,
+            TaskState::Succeeded => 1,
+            _ => 2,
+        }
+    }
+}
+
+pub fn migrate(conn: &Connection) -> rusqlite::Result<()> {
+    // WPS 记录ID, 用于判断同一行是否已经下发过
+    db::ensure_column(conn, "tasks", "row_id", "text not null default ''")?;
+    db::ensure_column(conn, "tasks", "state", "text not null default ''")?;
+    db::ensure_column(conn, "tasks", "started_at", "text")?;
+    db::ensure_column(conn, "tasks", "finished_at", "text")?;
+    db::ensure_column(conn, "tasks", "duration_ms", "integer")?;
+    // 下发给 automator 的内容与最后一次请求的响应, 用于排查渲染结果
+    db::ensure_column(conn, "tasks", "request_payload", "text")?;
+    db::ensure_column(conn, "tasks", "requested_at", "text")?;
+    db::ensure_column(conn, "tasks", "response_status", "integer")?;
+    db::ensure_column(conn, "tasks", "response_body", "text")?;
+    db::ensure_column(conn, "tasks", "responded_at", "text")?;
+    db::ensure_column(conn, "tasks", "response_ms", "integer")?;
+    // 重新执行时创建的任务指向原任务
+    db::ensure_column(conn, "tasks", "parent_task_id", "text")?;
+    // 旧版本只有 status, 未结束的按执行中处理, 随后由 recover_interrupted 标记为失败
+    conn.execute(
+        "UPDATE tasks SET state = CASE status
+            WHEN 1 THEN 'succeeded' WHEN 2 THEN 'failed' ELSE 'running' END
+         WHERE state = ''",
+        (),
+    )?;
+    conn.execute(
+        "create index if not exists idx_tasks_row_id on tasks(row_id)",
+        (),
+    )?;
+    Ok(())
+}
+
+fn add_log(conn: &Connection, task_id: &str, message: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO task_logs (task_id, log_time, message) VALUES (?1, ?2, ?3)",
+        params![task_id, Local::now().to_rfc3339(), message],
+    )?;
+    Ok(())
+}
+
+/// 程序退出时仍未结束的任务不会再有结果, 启动时标记为取消或失败, 对应的行可以重新下发
+pub fn recover_interrupted(conn: &Connection) -> Result<usize, String> {
+    let interrupted: Vec<(String, String)> = {
+        let mut stmt = conn
+            .prepare("SELECT task_id, state FROM tasks WHERE state IN ('queued', 'running')")
+            .map_err(|e| format!("数据库查询失败: {}", e))?;
+        let rows = stmt
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|e| format!("数据库查询失败: {}", e))?;
+        rows.collect::<rusqlite::Result<_>>()
+            .map_err(|e| format!("数据库查询失败: {}", e))?
+    };
+    for (task_id, state) in &interrupted {
+        let (to, message) = if state == "queued" {
+            (TaskState::Cancelled, "程序退出时任务尚未开始, 标记为取消")
+        } else {
+            (TaskState::Failed, "程序退出时任务未完成, 标记为失败")
+        };
+        transition(conn, task_id, to)?;
+        add_log(conn, task_id, message).map_err(|e| format!("日志插入失败: {}", e))?;
+    }
+    Ok(interrupted.len())
+}
+
+/// 该行已有排队中、执行中或已成功的任务时返回其 task_id.
+/// include_succeeded 为 false 时(重新下发)只检查排队中与执行中的任务
+pub fn find_active_task(
+    conn: &Connection,
+    row_id: &str,
+    include_succeeded: bool,
+) -> rusqlite::Result<Option<String>> {
+    if row_id.is_empty() {
+        return Ok(None);
+    }
+    conn.query_row(
+        "SELECT task_id FROM tasks
+         WHERE row_id = ?1
+           AND (state IN ('queued', 'running') OR (?2 AND state = 'succeeded'))
+         LIMIT 1",
+        params![row_id, include_succeeded],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// 新建任务所需的信息
+pub struct NewTask<'a> {
+    pub task_id: &'a str,
+    pub row_id: &'a str,
+    pub sku: &'a str,
+    pub module: &'a str,
+    pub create_time: &'a str,
+    /// 将要下发给 automator 的内容
+    pub payload: &'a serde_json::Value,
+    /// 重新执行时为原任务
+    pub parent_task_id: Option<&'a str>,
+}
+
+/// 新建排队中的任务
+pub fn insert_task(conn: &Connection, task: &NewTask) -> rusqlite::Result<()> {
+    let state = TaskState::Queued;
+    conn.execute(
+        "INSERT INTO tasks (task_id, run_time, SKU, module, create_time, status, row_id, state,
+                            request_payload, parent_task_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            task.task_id,
+            Local::now().to_rfc3339(),
+            task.sku,
+            task.module,
+            task.create_time,
+            state.legacy_status(),
+            task.row_id,
+            state.as_str(),
+            task.payload.to_string(),
+            task.parent_task_id
+        ],
+    )?;
+    Ok(())
+}
+
+/// 记录向 automator 发出请求的时间, 重试时覆盖上一次请求的响应
+pub fn record_request(conn: &Connection, task_id: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE tasks SET requested_at = ?1, response_status = NULL, response_body = NULL,
+                          responded_at = NULL, response_ms = NULL
+         WHERE task_id = ?2",
+        params![Local::now().to_rfc3339(), task_id],
+    )?;
+    Ok(())
+}
+
+/// 记录 automator 的 HTTP 状态与原始响应, 耗时从 record_request 记录的时间算起
+pub fn record_response(
+    conn: &Connection,
+    task_id: &str,
+    status: u16,
+    body: &str,
+) -> rusqlite::Result<()> {
+    let now = Local::now();
+    let requested_at: Option<String> = conn
+        .query_row(
+            "SELECT requested_at FROM tasks WHERE task_id = ?1",
+            params![task_id],
+            |row| row.get(0),
+        )
+        .optional()?
+        .flatten();
+    let response_ms = requested_at
+        .and_then(|s| DateTime::parse_from_rfc3339(&s).ok())
+        .map(|requested| (now - requested.with_timezone(&Local)).num_milliseconds());
+    conn.execute(
+        "UPDATE tasks SET response_status = ?1, response_body = ?2, responded_at = ?3,
+                          response_ms = ?4
+         WHERE task_id = ?5",
+        params![status, body, now.to_rfc3339(), response_ms, task_id],
+    )?;
+    Ok(())
+}
+
+/// 任务详情, 时间均为 RFC 3339 格式. payload 与 response_body 能解析为 JSON 时返回 JSON, 否则返回原文
+#[derive(Debug, Serialize)]
+pub struct TaskDetail {
+    pub task_id: String,
+    pub row_id: String,
+    pub sku: String,
+    pub module: String,
+    pub state: String,
+    pub run_time: String,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+    pub duration_ms: Option<i64>,
+    pub request_payload: Option<serde_json::Value>,
+    pub requested_at: Option<String>,
+    pub response_status: Option<u16>,
+    pub response_body: Option<serde_json::Value>,
+    pub responded_at: Option<String>,
+    pub response_ms: Option<i64>,
+}
+
+fn parse_stored(text: Option<String>) -> Option<serde_json::Value> {
+    text.map(|t| serde_json::from_str(&t).unwrap_or(serde_json::Value::String(t)))
+}
+
+pub fn load_detail(conn: &Connection, task_id: &str) -> rusqlite::Result<Option<TaskDetail>> {
+    conn.query_row(
+        "SELECT task_id, row_id, SKU, module, state, run_time, started_at, finished_at,
+                duration_ms, request_payload, requested_at, response_status, response_body,
+                responded_at, response_ms
+         FROM tasks WHERE task_id = ?1",
+        params![task_id],
+        |row| {
+            Ok(TaskDetail {
+                task_id: row.get(0)?,
+                row_id: row.get(1)?,
+                sku: row.get(2)?,
+                module: row.get(3)?,
+                state: row.get(4)?,
+                run_time: row.get(5)?,
+                started_at: row.get(6)?,
+                finished_at: row.get(7)?,
+                duration_ms: row.get(8)?,
+                request_payload: parse_stored(row.get(9)?),
+                requested_at: row.get(10)?,
+                response_status: row.get(11)?,
+                response_body: parse_stored(row.get(12)?),
+                responded_at: row.get(13)?,
+                response_ms: row.get(14)?,
+            })
+        },
+    )
+    .optional()
+}
+
+/// 按状态机修改任务状态, 进入执行中时记录开始时间, 进入结束状态时记录结束时间与耗时
+pub fn transition(conn: &Connection, task_id: &str, to: TaskState) -> Result<(), String> {
+    let (state, started_at): (String, Option<String>) = conn
+        .query_row(
+            "SELECT state, started_at FROM tasks WHERE task_id = ?1",
+            params![task_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .map_err(|e| format!("数据库查询失败: {}", e))?
+        .ok_or_else(|| format!("任务不存在: {}", task_id))?;
+    let from = TaskState::parse(&state).ok_or_else(|| format!("任务状态无效: {}", state))?;
+    if !from.can_transition(to) {
+        return Err(format!(
+            "任务 {} 的状态不能从 {} 变为 {}",
+            task_id,
+            from.as_str(),
+            to.as_str()
+        ));
+    }
+    let now = Local::now();
+    let result = if to == TaskState::Running {
+        conn.execute(
+            "UPDATE tasks SET state = ?1, status = ?2, started_at = ?3 WHERE task_id = ?4",
+            params![to.as_str(), to.legacy_status(), now.to_rfc3339(), task_id],
+        )
+    } else {
+        let duration_ms = started_at
+            .and_then(|s| DateTime::parse_from_rfc3339(&s).ok())
+            .map(|started| (now - started.with_timezone(&Local)).num_milliseconds());
+        conn.execute(
+            "UPDATE tasks SET state = ?1, status = ?2, finished_at = ?3, duration_ms = ?4
+             WHERE task_id = ?5",
+            params![
+                to.as_str(),
+                to.legacy_status(),
+                now.to_rfc3339(),
+                duration_ms,
+                task_id
+            ],
+        )
+    };
+    result.map_err(|e| format!("更新任务状态失败: {}", e))?;
+    Ok(())
+}
+
+/// 重新执行所需的原任务信息
+pub struct RerunSource {
+    pub row_id: String,
+    pub sku: String,
+    pub module: String,
+    pub create_time: String,
+    pub payload: serde_json::Value,
+}
+
+/// 读取已结束任务保存的下发内容. 只有失败、超时或已取消的任务可以重新执行
+pub fn rerun_source(conn: &Connection, task_id: &str) -> Result<RerunSource, String> {
+    let (row_id, sku, module, create_time, state, payload): (
+        String,
+        String,
+        String,
+        String,
+        String,
+        Option<String>,
+    ) = conn
+        .query_row(
+            "SELECT row_id, SKU, module, create_time, state, request_payload
+             FROM tasks WHERE task_id = ?1",
+            params![task_id],
+            |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                ))
+            },
+        )
+        .optional()
+        .map_err(|e| format!("数据库查询失败: {}", e))?
+        .ok_or_else(|| format!("任务不存在: {}", task_id))?;
+    if !matches!(
+        TaskState::parse(&state),
+        Some(TaskState::Failed | TaskState::TimedOut | TaskState::Cancelled)
+    ) {
+        return Err(format!(
+            "任务 {} 的状态为 {}, 只能重新执行失败、超时或已取消的任务",
+            task_id, state
+        ));
+    }
+    let payload =
+        payload.ok_or_else(|| format!("任务 {} 没有保存下发内容, 无法重新执行", task_id))?;
+    let payload = serde_json::from_str(&payload)
+        .map_err(|e| format!("任务 {} 保存的下发内容无法解析: {}", task_id, e))?;
+    Ok(RerunSource {
+        row_id,
+        sku,
+        module,
+        create_time,
+        payload,
+    })
+}
+
+/// 符合条件的失败与超时任务, 已经重新执行过的不再列出. sku 与 module 为模糊匹配, 与任务列表的查询条件一致
+pub fn failed_task_ids(
+    conn: &Connection,
+    sku: Option<&str>,
+    module: Option<&str>,
+) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT task_id FROM tasks
+         WHERE state IN ('failed', 'timed_out')
+           AND (?1 IS NULL OR SKU LIKE '%' || ?1 || '%')
+           AND (?2 IS NULL OR module LIKE '%' || ?2 || '%')
+           AND task_id NOT IN
+               (SELECT parent_task_id FROM tasks WHERE parent_task_id IS NOT NULL)
+         ORDER BY run_time",
+    )?;
+    let rows = stmt.query_map(params![sku, module], |row| row.get(0))?;
+    rows.collect()
+}
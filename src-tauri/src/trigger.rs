@@ -0,0 +1,405 @@
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDateTime, NaiveTime, TimeZone};
+use cron::Schedule as CronSchedule;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+/// 定时任务的触发方式, 以JSON保存在 schedules.trigger 中
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TriggerSpec {
+    /// cron表达式, 支持5段(分 时 日 月 周)或6/7段(含秒、年)
+    Cron { expr: String },
+    /// 从 start 开始每隔 every_minutes 分钟执行, 例如 09:03 起每7分钟
+    Interval {
+        start: NaiveDateTime,
+        every_minutes: u32,
+    },
+    /// 只在指定时间执行一次
+    Once { at: NaiveDateTime },
+    /// 每天 window_start ~ window_end(含)之间, 从 window_start 起每隔 every_minutes 分钟执行,
+    /// weekdays 为 1(周一) ~ 7(周日), 为空时每天执行
+    WindowedInterval {
+        every_minutes: u32,
+        window_start: NaiveTime,
+        window_end: NaiveTime,
+        #[serde(default)]
+        weekdays: Vec<u32>,
+    },
+}
+
+impl TriggerSpec {
+    /// 日志与界面展示用的简短说明
+    pub fn describe(&self) -> String {
+        match self {
+            TriggerSpec::Cron { expr } => expr.clone(),
+            TriggerSpec::Interval {
+                start,
+                every_minutes,
+            } => format!("{} 起每 {} 分钟", start, every_minutes),
+            TriggerSpec::Once { at } => format!("{} 执行一次", at),
+            TriggerSpec::WindowedInterval {
+                every_minutes,
+                window_start,
+                window_end,
+                weekdays,
+            } => {
+                let days = if weekdays.is_empty() {
+                    "每天".to_string()
+                } else {
+                    format!("每周{:?}", weekdays)
+                };
+                format!(
+                    "{} {} ~ {} 每 {} 分钟",
+                    days, window_start, window_end, every_minutes
+                )
+            }
+        }
+    }
+}
+
+/// 由 TriggerSpec 校验得到, 用于计算触发时间
+pub enum Trigger {
+    Cron(Box<CronSchedule>),
+    Interval {
+        start: DateTime<Local>,
+        every: Duration,
+    },
+    Once(DateTime<Local>),
+    WindowedInterval {
+        every: Duration,
+        window_start: NaiveTime,
+        window_end: NaiveTime,
+        weekdays: Vec<u32>,
+    },
+}
+
+impl Trigger {
+    pub fn from_spec(spec: &TriggerSpec) -> Result<Self, String> {
+        let every = |minutes: u32| {
+            if minutes == 0 {
+                Err("间隔分钟数必须大于0".to_string())
+            } else {
+                Ok(Duration::minutes(minutes as i64))
+            }
+        };
+        match spec {
+            TriggerSpec::Cron { expr } => Ok(Trigger::Cron(Box::new(parse_cron(expr)?))),
+            TriggerSpec::Interval {
+                start,
+                every_minutes,
+            } => Ok(Trigger::Interval {
+                start: to_local(start).ok_or_else(|| format!("开始时间无效: {}", start))?,
+                every: every(*every_minutes)?,
+            }),
+            TriggerSpec::Once { at } => Ok(Trigger::Once(
+                to_local(at).ok_or_else(|| format!("执行时间无效: {}", at))?,
+            )),
+            TriggerSpec::WindowedInterval {
+                every_minutes,
+                window_start,
+                window_end,
+                weekdays,
+            } => {
+                if window_end <= window_start {
+                    return Err(format!("执行时段无效: {} ~ {}", window_start, window_end));
+                }
+                if weekdays.iter().any(|d| !(1..=7).contains(d)) {
+                    return Err("星期只能为 1(周一) ~ 7(周日)".to_string());
+                }
+                Ok(Trigger::WindowedInterval {
+                    every: every(*every_minutes)?,
+                    window_start: *window_start,
+                    window_end: *window_end,
+                    weekdays: weekdays.clone(),
+                })
+            }
+        }
+    }
+
+    /// 严格晚于 t 的下一次触发时间, 没有后续触发时返回 None
+    pub fn after(&self, t: &DateTime<Local>) -> Option<DateTime<Local>> {
+        match self {
+            Trigger::Cron(cron_schedule) => cron_schedule.after(t).next(),
+            Trigger::Interval { start, every } => {
+                if t < start {
+                    return Some(*start);
+                }
+                let steps = (*t - *start).num_seconds() / every.num_seconds() + 1;
+                Some(*start + *every * steps as i32)
+            }
+            Trigger::Once(at) => (at > t).then_some(*at),
+            Trigger::WindowedInterval {
+                every,
+                window_start,
+                window_end,
+                weekdays,
+            } => {
+                let mut date = t.date_naive();
+                // 最多向后查找一周多, 保证跨过未选中的星期
+                for _ in 0..8 {
+                    if weekdays.is_empty()
+                        || weekdays.contains(&date.weekday().number_from_monday())
+                    {
+                        let mut time = *window_start;
+                        loop {
+                            if let Some(candidate) = to_local(&date.and_time(time)) {
+                                if candidate > *t {
+                                    return Some(candidate);
+                                }
+                            }
+                            match time.overflowing_add_signed(*every) {
+                                (next, 0) if next <= *window_end => time = next,
+                                _ => break,
+                            }
+                        }
+                    }
+                    date = date.succ_opt()?;
+                }
+                None
+            }
+        }
+    }
+}
+
+/// 本地时间转换, 夏令时跳过的时间返回 None, 重复的时间取较早的一次
+fn to_local(t: &NaiveDateTime) -> Option<DateTime<Local>> {
+    Local.from_local_datetime(t).earliest()
+}
+
+/// cron crate 中星期的英文缩写, 下标为标准cron的星期数字(0 为周日)
+const WEEKDAY_NAMES: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+/// 前端生成的是5段式cron, cron crate需要带秒的6段式.
+/// 标准cron的星期 1 为周一、0 或 7 为周日, 与 WindowedInterval 和屏蔽日历一致;
+/// cron crate 中 1 为周日, 因此星期中的数字转换为英文缩写
+pub fn normalize_cron(expr: &str) -> String {
+    let fields: Vec<&str> = expr.split_whitespace().collect();
+    if let [minute, hour, day, month, weekday] = fields[..] {
+        format!(
+            "0 {} {} {} {} {}",
+            minute,
+            hour,
+            day,
+            month,
+            normalize_weekdays(weekday)
+        )
+    } else {
+        expr.to_string()
+    }
+}
+
+fn normalize_weekdays(field: &str) -> String {
+    let mut days = BTreeSet::new();
+    let mut others = Vec::new();
+    for item in field.split(',') {
+        match weekday_numbers(item) {
+            Some(numbers) => days.extend(numbers),
+            None => others.push(item),
+        }
+    }
+    days.into_iter()
+        .map(|d| WEEKDAY_NAMES[d])
+        .chain(others)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// 标准cron星期中的一项(数字、范围、带步长的范围)包含的星期, 0 为周日.
+/// 其余写法(*、?、英文缩写)原样交给 cron crate
+fn weekday_numbers(item: &str) -> Option<BTreeSet<usize>> {
+    let (range, step) = match item.split_once('/') {
+        Some((range, step)) => (range, step.parse::<usize>().ok().filter(|s| *s > 0)?),
+        None => (item, 1),
+    };
+    let (start, end) = match range.split_once('-') {
+        _ if range == "*" && step > 1 => (0, 6),
+        Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+        None => {
+            let start = range.parse().ok()?;
+            (start, if step > 1 { 7 } else { start })
+        }
+    };
+    if end > 7 || start > end {
+        return None;
+    }
+    Some((start..=end).step_by(step).map(|d| d % 7).collect())
+}
+
+/// cron表达式校验失败的详细信息, 供前端展示
+#[derive(Debug, Serialize)]
+pub struct CronError {
+    pub expression: String,
+    pub normalized: String,
+    pub field_count: usize,
+    pub message: String,
+}
+
+pub fn validate_cron(expr: &str) -> Result<CronSchedule, CronError> {
+    let normalized = normalize_cron(expr.trim());
+    let field_count = expr.split_whitespace().count();
+    let error = |message: String| CronError {
+        expression: expr.to_string(),
+        normalized: normalized.clone(),
+        field_count,
+        message,
+    };
+    if !(5..=7).contains(&field_count) {
+        return Err(error(format!(
+            "cron表达式应为5段(分 时 日 月 周)或6/7段(含秒、年), 实际为{}段",
+            field_count
+        )));
+    }
+    CronSchedule::from_str(&normalized).map_err(|e| error(e.to_string()))
+}
+
+pub fn parse_cron(expr: &str) -> Result<CronSchedule, String> {
+    validate_cron(expr).map_err(|e| format!("解析cron表达式失败:{}", e.message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2026-06-08 为周一
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2026, 6, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn windowed(weekdays: Vec<u32>) -> Trigger {
+        Trigger::from_spec(&TriggerSpec::WindowedInterval {
+            every_minutes: 30,
+            window_start: time(9, 0),
+            window_end: time(10, 0),
+            weekdays,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn interval_steps_from_start() {
+        let trigger = Trigger::from_spec(&TriggerSpec::Interval {
+            start: at(10, 9, 3).naive_local(),
+            every_minutes: 7,
+        })
+        .unwrap();
+        assert_eq!(trigger.after(&at(10, 8, 0)), Some(at(10, 9, 3)));
+        assert_eq!(trigger.after(&at(10, 9, 3)), Some(at(10, 9, 10)));
+        assert_eq!(trigger.after(&at(10, 9, 12)), Some(at(10, 9, 17)));
+        assert_eq!(trigger.after(&at(10, 9, 17)), Some(at(10, 9, 24)));
+        // 09:03 + 7 * 30
+        assert_eq!(trigger.after(&at(10, 12, 32)), Some(at(10, 12, 33)));
+    }
+
+    #[test]
+    fn interval_rejects_zero_minutes() {
+        let spec = TriggerSpec::Interval {
+            start: at(10, 9, 0).naive_local(),
+            every_minutes: 0,
+        };
+        assert!(Trigger::from_spec(&spec).is_err());
+    }
+
+    #[test]
+    fn windowed_interval_includes_window_end() {
+        let trigger = windowed(Vec::new());
+        assert_eq!(trigger.after(&at(10, 8, 0)), Some(at(10, 9, 0)));
+        assert_eq!(trigger.after(&at(10, 9, 0)), Some(at(10, 9, 30)));
+        assert_eq!(trigger.after(&at(10, 9, 45)), Some(at(10, 10, 0)));
+        assert_eq!(trigger.after(&at(10, 10, 0)), Some(at(11, 9, 0)));
+    }
+
+    #[test]
+    fn windowed_interval_skips_unselected_weekdays() {
+        // 周一、周三
+        let trigger = windowed(vec![1, 3]);
+        assert_eq!(trigger.after(&at(8, 10, 0)), Some(at(10, 9, 0)));
+        // 周六之后为下周一
+        assert_eq!(trigger.after(&at(13, 12, 0)), Some(at(15, 9, 0)));
+    }
+
+    #[test]
+    fn windowed_interval_crosses_into_next_week() {
+        // 只选周一, 周一最后一次触发之后为下周一
+        let trigger = windowed(vec![1]);
+        assert_eq!(trigger.after(&at(8, 10, 0)), Some(at(15, 9, 0)));
+        assert_eq!(trigger.after(&at(14, 23, 0)), Some(at(15, 9, 0)));
+    }
+
+    #[test]
+    fn windowed_interval_validates_window_and_weekdays() {
+        let spec = |window_end: NaiveTime, weekdays: Vec<u32>| TriggerSpec::WindowedInterval {
+            every_minutes: 30,
+            window_start: time(9, 0),
+            window_end,
+            weekdays,
+        };
+        assert!(Trigger::from_spec(&spec(time(9, 0), Vec::new())).is_err());
+        assert!(Trigger::from_spec(&spec(time(10, 0), vec![0])).is_err());
+        assert!(Trigger::from_spec(&spec(time(10, 0), vec![8])).is_err());
+    }
+
+    #[test]
+    fn once_fires_only_before_its_time() {
+        let trigger = Trigger::from_spec(&TriggerSpec::Once {
+            at: at(10, 9, 0).naive_local(),
+        })
+        .unwrap();
+        assert_eq!(trigger.after(&at(10, 8, 59)), Some(at(10, 9, 0)));
+        assert_eq!(trigger.after(&at(10, 9, 0)), None);
+        assert_eq!(trigger.after(&at(11, 9, 0)), None);
+    }
+
+    #[test]
+    fn cron_weekdays_use_standard_numbering() {
+        assert_eq!(
+            normalize_cron("0 9 * * 1-5"),
+            "0 0 9 * * MON,TUE,WED,THU,FRI"
+        );
+        assert_eq!(normalize_cron("0 9 * * 0,7"), "0 0 9 * * SUN");
+        assert_eq!(normalize_cron("0 9 * * */2"), "0 0 9 * * SUN,TUE,THU,SAT");
+        assert_eq!(normalize_cron("0 9 * * 1/3"), "0 0 9 * * SUN,MON,THU");
+        assert_eq!(normalize_cron("0 9 * * MON-FRI"), "0 0 9 * * MON-FRI");
+        // 6段式已经是 cron crate 的写法, 不转换
+        assert_eq!(normalize_cron("0 0 9 * * 1"), "0 0 9 * * 1");
+
+        // 界面上的“周一~周五”
+        let trigger = Trigger::from_spec(&TriggerSpec::Cron {
+            expr: "0 9 * * 1,2,3,4,5".to_string(),
+        })
+        .unwrap();
+        // 周五之后为下周一
+        assert_eq!(trigger.after(&at(12, 10, 0)), Some(at(15, 9, 0)));
+        let mut cursor = at(7, 23, 0);
+        let fires: Vec<u32> = (0..5)
+            .map(|_| {
+                cursor = trigger.after(&cursor).unwrap();
+                cursor.weekday().number_from_monday()
+            })
+            .collect();
+        assert_eq!(fires, vec![1, 2, 3, 4, 5]);
+
+        let sunday = Trigger::from_spec(&TriggerSpec::Cron {
+            expr: "0 9 * * 7".to_string(),
+        })
+        .unwrap();
+        assert_eq!(sunday.after(&at(8, 0, 0)), Some(at(14, 9, 0)));
+    }
+
+    #[test]
+    fn cron_accepts_five_fields() {
+        assert_eq!(normalize_cron("0 2 * * *"), "0 0 2 * * *");
+        let trigger = Trigger::from_spec(&TriggerSpec::Cron {
+            expr: "0 2 * * *".to_string(),
+        })
+        .unwrap();
+        assert_eq!(trigger.after(&at(10, 2, 0)), Some(at(11, 2, 0)));
+        assert!(validate_cron("0 2 * *").is_err());
+    }
+}
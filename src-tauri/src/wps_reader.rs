@@ -1,134 +1,178 @@
 use log::{error, info};
-use reqwest::{header, Error};
+use reqwest::header;
+use serde::Deserialize;
 use serde_json::json;
-use std::env;
-use dotenv::dotenv;
+use std::fmt;
+use std::time::Duration;
 
-pub async fn fetch_wps_data() -> Result<String, Error> {
-    dotenv().ok();
-    let url = env::var("wps_url").expect("WPS_URL must be set in .env file");
+use crate::filter::Criterion;
+use crate::models::WpsResponse;
+use crate::retry::{self, Failure};
+use crate::settings::{ConnectionSettings, WpsMapping};
+
+/// 读取或回写 WPS 的错误
+#[derive(Debug)]
+pub enum WpsError {
+    /// 连接设置未填写或有误, 修改设置前重试也不会成功
+    Config(String),
+    Request(reqwest::Error),
+    /// Airscript 返回了错误或格式不正确的响应
+    Response(String),
+}
+
+impl fmt::Display for WpsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WpsError::Config(message) => f.write_str(message),
+            WpsError::Request(e) => write!(f, "{}", e),
+            WpsError::Response(message) => f.write_str(message),
+        }
+    }
+}
+
+impl From<reqwest::Error> for WpsError {
+    fn from(e: reqwest::Error) -> Self {
+        WpsError::Request(e)
+    }
+}
+
+impl WpsError {
+    /// 按是否可以重试分类, context 为错误信息前缀
+    pub fn classify(&self, context: &str) -> Failure {
+        match self {
+            WpsError::Config(message) | WpsError::Response(message) => {
+                Failure::Permanent(format!("{}: {}", context, message))
+            }
+            WpsError::Request(e) => retry::classify(context, e),
+        }
+    }
+}
+
+/// 按连接设置创建请求 Airscript 的请求
+fn airscript_request(connection: &ConnectionSettings) -> Result<reqwest::RequestBuilder, WpsError> {
+    connection.check_wps().map_err(WpsError::Config)?;
+    let token = header::HeaderValue::from_str(&connection.airscript_token)
+        .map_err(|_| WpsError::Config("Airscript Token 含有无效字符".to_string()))?;
     let client = reqwest::Client::new();
     let mut headers = reqwest::header::HeaderMap::new();
-    let token = env::var("Airscript_Token").expect("Airscript-Token must be set in .env file");
-    headers.insert(
-        "Airscript-Token",
-        header::HeaderValue::from_str(&token).expect("Invalid token"),
-    );
+    headers.insert("Airscript-Token", token);
     headers.insert(
         "Content-Type",
         header::HeaderValue::from_static("application/json"),
     );
+    Ok(client
+        .post(&connection.wps_url)
+        .headers(headers)
+        .timeout(Duration::from_secs(connection.wps_timeout_secs)))
+}
 
+/// 读取一页可运行且尚未回写执行结果的行, criteria 为额外附加的 AND 条件.
+/// offset 为上一页返回的游标, 第一页为 None; 返回的 offset 为空时表示没有下一页.
+/// 不支持分页的 Airscript 会忽略 pageSize 与 offset, 一次返回全部记录
+pub async fn fetch_wps_data(
+    connection: &ConnectionSettings,
+    mapping: &WpsMapping,
+    criteria: &[&Criterion],
+    page_size: usize,
+    offset: Option<&str>,
+) -> Result<WpsResponse, WpsError> {
+    let request = airscript_request(connection)?;
+    let mut all_criteria = vec![
+        json!({
+            "field":mapping.eligible_column,
+            "op":"Equals",
+            "values":[mapping.eligible_value]
+        }),
+        json!({
+            "field":mapping.result_column,
+            "op":"Equals",
+            "values":[""]
+        }),
+    ];
+    all_criteria.extend(criteria.iter().map(|c| json!(c)));
     let payload = json!(
         {
             "Context":{
                 "argv":{
                     "action":"search",
-                    "sheet":"数据表",
+                    "sheet":mapping.sheet,
+                    "pageSize":page_size,
+                    "offset":offset,
                     "filter":{
                         "mode":"AND",
-                        "criteria":[
-                            {
-                                "field":"设计师确认是否已完整填写可运行",
-                                "op":"Equals",
-                                "values":[
-                                    "是"
-                                ]
-                            },
-                            {
-                                "field":"是否运行完成",
-                                "op":"Equals",
-                                "values":[""]
-                            }
-                        ]
+                        "criteria":all_criteria
                     }
                 }
             }
         }
     );
 
-    let response = client
-        .post(url)
-        .headers(headers)
-        .json(&payload)
-        .send()
-        .await?;
+    // 先检查状态码, 5xx 的响应体通常不是 JSON, 按状态码分类才能重试
+    let response = request.json(&payload).send().await?.error_for_status()?;
 
     let response_json: serde_json::Value = response.json().await?;
-    response_json.get("data").map_or_else(
-        || {
-            error!("数据获取失败: 响应体中没有 'data' 字段");
-            let error_message = json!({
-                "status": "Error",
-                "message": "数据获取失败"
-            });
-            Ok(error_message.to_string())
-        },
-        |data| {
-            let success_message = json!({
-                "status": "Success",
-                "data": data.get("result").and_then(|result| result.get("data"))
-            });
-            Ok(success_message.to_string())
-        },
-    )
+    check_script(&response_json)?;
+    let result = response_json.pointer("/data/result").ok_or_else(|| {
+        error!("数据获取失败: 响应体中没有 'data' 字段");
+        WpsError::Response("响应中没有 data 字段".to_string())
+    })?;
+    WpsResponse::deserialize(result).map_err(|e| {
+        error!("数据获取失败: 响应格式不正确: {}", e);
+        WpsError::Response(format!("响应格式不正确: {}", e))
+    })
 }
 
-pub async fn update_wps_date(target_id: &str, res: &str) -> Result<String, Error> {
-    dotenv().ok();
-    let url = env::var("wps_url").expect("WPS_URL must be set in .env file");
-    let client = reqwest::Client::new();
-    let mut headers = reqwest::header::HeaderMap::new();
-    let token = env::var("Airscript_Token").expect("Airscript-Token must be set in .env file");
-    headers.insert(
-        "Airscript-Token",
-        header::HeaderValue::from_str(&token).expect("Invalid token"),
-    );
-    headers.insert(
-        "Content-Type",
-        header::HeaderValue::from_static("application/json"),
-    );
+/// 回写执行结果, 回写值由表格映射决定
+pub async fn update_wps_date(
+    connection: &ConnectionSettings,
+    mapping: &WpsMapping,
+    target_id: &str,
+    success: bool,
+) -> Result<(), WpsError> {
+    let request = airscript_request(connection)?;
+    let res = if success {
+        &mapping.result_success
+    } else {
+        &mapping.result_failure
+    };
+    let mut fields = serde_json::Map::new();
+    fields.insert(mapping.result_column.clone(), json!(res));
     let payload = json!({
       "Context": {
         "argv": {
           "action": "update",
-          "sheet": "数据表",
+          "sheet": mapping.sheet,
           "records": [
             {
-              "fields":
-                {
-                  "是否运行完成": res
-                }
-              ,
+              "fields": fields,
               "id": target_id
             }
           ]
         }
       }
     });
-    let response = client
-        .post(url)
-        .headers(headers)
-        .json(&payload)
-        .send()
-        .await?;
+    // 先检查状态码, 5xx 的响应体通常不是 JSON, 按状态码分类才能重试
+    let response = request.json(&payload).send().await?.error_for_status()?;
     let response_json: serde_json::Value = response.json().await?;
-    response_json.get("status").map_or_else(
-        || {
-            error!("解析失败没有获取到status字段");
-            let error_message = json!({
-                "status": "Error",
-                "message": "更新失败"
-            });
-            Ok(error_message.to_string())
-        },
-        |status| {
-            info!("更新成功: {}", status);
-            let success_message = json!({
-                "status": "Success",
-                "message": "更新成功"
-            });
-            Ok(success_message.to_string())
-        },
-    )
+    check_script(&response_json)?;
+    info!("更新成功: {}", target_id);
+    Ok(())
+}
+
+/// 检查 Airscript 的响应, 没有 status 字段或脚本报错时返回错误
+fn check_script(response: &serde_json::Value) -> Result<(), WpsError> {
+    if response.get("status").is_none() {
+        error!("解析失败没有获取到status字段: {}", response);
+        return Err(WpsError::Response("响应中没有 status 字段".to_string()));
+    }
+    let script_error = response
+        .get("error")
+        .or_else(|| response.pointer("/data/error"))
+        .and_then(|e| e.as_str())
+        .filter(|e| !e.is_empty());
+    if let Some(e) = script_error {
+        error!("Airscript 执行失败: {}", e);
+        return Err(WpsError::Response(format!("Airscript 执行失败: {}", e)));
+    }
+    Ok(())
 }